@@ -0,0 +1,130 @@
+#!/usr/bin/env python3
+"""1 回の呼び出しあたりのプロキシのオーバーヘッドを測る。
+
+keep-alive のバックエンド (Lambda のレスポンスを返すだけ) を起動し、同じ数の
+リクエストを直接とプロキシ経由で順に送って、レイテンシの差をオーバーヘッドとする。
+
+    cargo build --release
+    python3 scripts/bench.py [-n 1000] [--binary target/release/aws-lambda-proxy]
+"""
+
+import argparse
+import http.client
+import json
+import os
+import socket
+import subprocess
+import sys
+import threading
+import time
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+
+PAYLOAD = json.dumps({"statusCode": 200, "body": "ok"}).encode()
+
+
+class Backend(BaseHTTPRequestHandler):
+    protocol_version = "HTTP/1.1"
+    # ヘッダーとボディを別々に書くので、Nagle と遅延 ACK で 40ms 待たないようにする
+    disable_nagle_algorithm = True
+
+    def do_GET(self):
+        self.reply()
+
+    def do_POST(self):
+        self.rfile.read(int(self.headers.get("Content-Length", 0)))
+        self.reply()
+
+    def reply(self):
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(PAYLOAD)))
+        self.end_headers()
+        self.wfile.write(PAYLOAD)
+
+    def log_message(self, *args):
+        pass
+
+
+def free_port():
+    with socket.socket() as s:
+        s.bind(("127.0.0.1", 0))
+        return s.getsockname()[1]
+
+
+def wait_for(port):
+    for _ in range(100):
+        try:
+            socket.create_connection(("127.0.0.1", port)).close()
+            return
+        except OSError:
+            time.sleep(0.05)
+    sys.exit(f"port {port} did not open")
+
+
+def measure(port, n, warmup):
+    connection = http.client.HTTPConnection("127.0.0.1", port)
+    connection.connect()
+    connection.sock.setsockopt(socket.IPPROTO_TCP, socket.TCP_NODELAY, 1)
+    latencies = []
+    for i in range(warmup + n):
+        started = time.perf_counter()
+        connection.request("GET", "/bench?i=%d" % i)
+        response = connection.getresponse()
+        response.read()
+        if response.status != 200:
+            sys.exit(f"unexpected status {response.status} from port {port}")
+        if i >= warmup:
+            latencies.append(time.perf_counter() - started)
+    latencies.sort()
+    return latencies
+
+
+def percentile(latencies, p):
+    return latencies[min(len(latencies) - 1, int(len(latencies) * p))] * 1000
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("-n", type=int, default=1000, help="リクエスト数")
+    parser.add_argument("--warmup", type=int, default=100)
+    parser.add_argument("--binary", default="target/release/aws-lambda-proxy")
+    args = parser.parse_args()
+
+    backend = ThreadingHTTPServer(("127.0.0.1", 0), Backend)
+    threading.Thread(target=backend.serve_forever, daemon=True).start()
+    backend_port = backend.server_address[1]
+
+    proxy_port = free_port()
+    env = {
+        "PATH": os.environ.get("PATH", ""),
+        "BACKEND": f"http://127.0.0.1:{backend_port}",
+        "RUST_LOG": "error",
+    }
+    proxy = subprocess.Popen(
+        [args.binary, "serve", "--listen", f"127.0.0.1:{proxy_port}"],
+        env=env,
+        stdout=subprocess.DEVNULL,
+        stderr=subprocess.DEVNULL,
+    )
+    try:
+        wait_for(proxy_port)
+        direct = measure(backend_port, args.n, args.warmup)
+        proxied = measure(proxy_port, args.n, args.warmup)
+    finally:
+        proxy.terminate()
+        proxy.wait()
+        backend.shutdown()
+
+    print(f"{'':8} {'p50':>8} {'p99':>8}")
+    for name, latencies in [("direct", direct), ("proxy", proxied)]:
+        print(
+            f"{name:8} {percentile(latencies, 0.5):7.2f}ms {percentile(latencies, 0.99):7.2f}ms"
+        )
+    print(
+        f"{'overhead':8} {percentile(proxied, 0.5) - percentile(direct, 0.5):7.2f}ms "
+        f"{percentile(proxied, 0.99) - percentile(direct, 0.99):7.2f}ms"
+    )
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,94 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, StatusCode},
+};
+use bytes::Bytes;
+use futures_util::Stream;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::error::ProxyError;
+use crate::runtime_api::RuntimeApi;
+
+/// イベントを処理するバックエンド
+pub enum Backend {
+    /// Runtime Interface Emulator などの HTTP エンドポイントに POST する
+    Http {
+        url: String,
+        /// 全てのバックエンドで共有し、接続を再利用する
+        client: reqwest::Client,
+    },
+    /// 組み込みの Runtime API に接続してきた関数に渡す
+    Runtime(Arc<RuntimeApi>),
+}
+
+/// バックエンドからの応答 (Runtime Interface Emulator の Invoke のレスポンスに相当)
+pub struct BackendResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Body,
+}
+
+impl Backend {
+    /// timeout までに応答がなければ ProxyError::Timeout を返す。
+    /// 応答と、そのボディを読み終えるべき期限を返す
+    pub async fn invoke(
+        &self,
+        event: Vec<u8>,
+        request_id: &str,
+        timeout: Duration,
+    ) -> Result<(BackendResponse, tokio::time::Instant), ProxyError> {
+        let (response, deadline) = match self {
+            Backend::Http { url, client } => {
+                let started = Instant::now();
+                let deadline = tokio::time::Instant::now() + timeout;
+                let response =
+                    tokio::time::timeout_at(deadline, client.post(url).body(event).send())
+                        .await
+                        .map_err(|_| ProxyError::Timeout(timeout))?
+                        .map_err(|e| ProxyError::Backend(format!("{}: {}", url, e)))?;
+                tracing::debug!("{} responded in {:?}", url, started.elapsed());
+                let response = BackendResponse {
+                    status: response.status(),
+                    headers: response.headers().clone(),
+                    body: Body::from_stream(chunk_stream(response)),
+                };
+                (response, deadline)
+            }
+            // 関数がイベントを受け取った時点から数える
+            Backend::Runtime(api) => api.invoke(event, request_id, timeout).await?,
+        };
+
+        if response.status.is_server_error() {
+            let body = axum::body::to_bytes(response.body, usize::MAX)
+                .await
+                .unwrap_or_default();
+            return Err(ProxyError::Backend(format!(
+                "backend returned {}: {}",
+                response.status,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        Ok((response, deadline))
+    }
+
+    /// タイムアウトした実行環境を作り直す。プロキシが起動した関数のプロセスだけが対象
+    pub fn recycle(&self) {
+        if let Backend::Runtime(api) = self {
+            api.recycle();
+        }
+    }
+}
+
+/// reqwest のレスポンスをチャンクが届いた順に流す
+fn chunk_stream(response: reqwest::Response) -> impl Stream<Item = Result<Bytes, reqwest::Error>> {
+    futures_util::stream::unfold(response, |mut response| async move {
+        response
+            .chunk()
+            .await
+            .transpose()
+            .map(|chunk| (chunk, response))
+    })
+}
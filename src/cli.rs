@@ -0,0 +1,392 @@
+use std::{env, net::SocketAddr, path::PathBuf, str::FromStr};
+
+use crate::config::{self, Config, FunctionConfig, ListenerConfig, Parsed, RouteConfig};
+use crate::event::EventFormat;
+use crate::function::timeout_from_secs;
+
+pub const USAGE: &str = "\
+Usage: aws-lambda-proxy [OPTIONS] [COMMAND]
+
+Commands:
+  serve                      HTTP リクエストを Lambda のイベントに変換して関数を呼び出す (デフォルト)
+  invoke [--function NAME] <EVENT_FILE>
+                             イベントファイル (- は標準入力) で関数を 1 回呼び出し、レスポンスを出力する
+  validate-config            設定を検証して終了する
+
+Options:
+  -c, --config <PATH>        設定ファイル (環境変数 CONFIG)
+  -l, --listen <ADDR>        待ち受けるアドレス (例: 0.0.0.0:8000)。複数指定できる
+  -b, --backend <URL>        $default ルートで呼び出す関数の URL。複数指定すると並列に呼び出す
+      --event-format <FORMAT>
+                             v2, v1, alb, alb-multi-value
+      --timeout <SECONDS>    関数のタイムアウト (1〜900)
+      --log-format <FORMAT>  text (デフォルト) または json
+  -h, --help                 このヘルプを表示する
+  -V, --version              バージョンを表示する
+
+指定しなかった項目は設定ファイルか環境変数の値を使う。";
+
+pub enum Command {
+    Serve,
+    Invoke {
+        event_file: PathBuf,
+        /// 呼び出す関数の名前。None は $default ルートの関数
+        function: Option<String>,
+    },
+    ValidateConfig,
+    Help,
+    Version,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format: {}", s)),
+        }
+    }
+}
+
+/// コマンドライン引数。オプションはサブコマンドの前後どちらにも書ける
+pub struct Cli {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub listen: Vec<SocketAddr>,
+    pub backends: Vec<String>,
+    pub event_format: Option<EventFormat>,
+    pub timeout: Option<u64>,
+    pub log_format: LogFormat,
+}
+
+impl Cli {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+        let mut cli = Cli {
+            command: Command::Serve,
+            config: None,
+            listen: Vec::new(),
+            backends: Vec::new(),
+            event_format: None,
+            timeout: None,
+            log_format: LogFormat::Text,
+        };
+        let mut command: Option<String> = None;
+        let mut positional = Vec::new();
+        let mut function = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // --name=value と --name value の両方を受け付ける
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || -> Result<String, String> {
+                match inline_value {
+                    Some(value) => Ok(value.to_string()),
+                    None => args
+                        .next()
+                        .ok_or_else(|| format!("{} requires a value", name)),
+                }
+            };
+            match name.as_str() {
+                "-h" | "--help" => cli.command = Command::Help,
+                "-V" | "--version" => cli.command = Command::Version,
+                "-c" | "--config" => cli.config = Some(PathBuf::from(value()?)),
+                "-l" | "--listen" => {
+                    let addr = value()?;
+                    cli.listen.push(
+                        addr.parse()
+                            .map_err(|_| format!("--listen: invalid address: {}", addr))?,
+                    );
+                }
+                "-b" | "--backend" => cli.backends.push(value()?),
+                "--event-format" => {
+                    cli.event_format = Some(
+                        value()?
+                            .parse()
+                            .map_err(|e| format!("--event-format: {}", e))?,
+                    )
+                }
+                "--timeout" => {
+                    let seconds = value()?;
+                    let valid = seconds
+                        .parse()
+                        .map_err(|_| "must be a number of seconds".to_string())
+                        .and_then(|seconds| timeout_from_secs(seconds).map(|_| seconds));
+                    match valid {
+                        Ok(seconds) => cli.timeout = Some(seconds),
+                        Err(e) => return Err(format!("--timeout: {}: {}", e, seconds)),
+                    }
+                }
+                "--log-format" => {
+                    cli.log_format = value()?
+                        .parse()
+                        .map_err(|e| format!("--log-format: {}", e))?
+                }
+                "-f" | "--function" => function = Some(value()?),
+                "-" => positional.push(arg),
+                _ if name.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ if command.is_none() => command = Some(arg),
+                _ => positional.push(arg),
+            }
+        }
+        if matches!(cli.command, Command::Help | Command::Version) {
+            return Ok(cli);
+        }
+
+        // サブコマンドの前に書かれることもあるので、解析し終えてから判定する
+        if function.is_some() && command.as_deref() != Some("invoke") {
+            return Err("--function can only be used with invoke".to_string());
+        }
+        let mut positional = positional.into_iter();
+        cli.command = match command.as_deref() {
+            None | Some("serve") => Command::Serve,
+            Some("invoke") => Command::Invoke {
+                event_file: positional
+                    .next()
+                    .map(PathBuf::from)
+                    .ok_or("invoke requires an event file")?,
+                function,
+            },
+            Some("validate-config") => Command::ValidateConfig,
+            Some(command) => return Err(format!("unknown command: {}", command)),
+        };
+        if let Some(arg) = positional.next() {
+            return Err(format!("unexpected argument: {}", arg));
+        }
+        Ok(cli)
+    }
+
+    /// 設定ファイル (なければ環境変数) の設定に引数の値を上書きする
+    pub fn config(&self) -> Result<Config, String> {
+        let path = self
+            .config
+            .clone()
+            .or_else(|| env::var("CONFIG").ok().map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => config::load(&path)?,
+            None => Config::from_env()?,
+        };
+
+        if !self.listen.is_empty() {
+            config.listeners = self
+                .listen
+                .iter()
+                .map(|addr| ListenerConfig {
+                    address: Parsed(*addr),
+                    tls: None,
+                })
+                .collect();
+        }
+        if !self.backends.is_empty() {
+            // BACKEND と同じく URL を関数名とし、$default ルートを置き換える
+            let name = self.backends.join(",");
+            let replaced = config
+                .routes
+                .iter()
+                .position(|route| route.route == "$default")
+                .map(|i| config.routes.remove(i).function);
+            // 他のルートから参照されていなければ元の関数は起動しない
+            if let Some(replaced) = replaced
+                && !config.routes.iter().any(|route| route.function == replaced)
+            {
+                config.functions.remove(&replaced);
+            }
+            config
+                .functions
+                .insert(name.clone(), FunctionConfig::http(&name));
+            config.routes.push(RouteConfig::new("$default", &name));
+        }
+        config.overrides.format = self.event_format;
+        config.overrides.timeout = self.timeout;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    fn parse_err(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("expected an error: {:?}", args),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn default_command_is_serve() {
+        let cli = parse(&[]).unwrap();
+        assert!(matches!(cli.command, Command::Serve));
+        assert!(cli.config.is_none());
+        assert!(cli.listen.is_empty());
+        assert!(cli.timeout.is_none());
+        assert!(cli.log_format == LogFormat::Text);
+    }
+
+    #[test]
+    fn inline_and_separate_values() {
+        let cli = parse(&[
+            "--config=proxy.yaml",
+            "--listen",
+            "127.0.0.1:8080",
+            "-l",
+            "127.0.0.1:8081",
+            "--backend=http://127.0.0.1:9000/a=b",
+            "--timeout=10",
+            "--event-format",
+            "v1",
+            "--log-format=json",
+        ])
+        .unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("proxy.yaml")));
+        assert_eq!(
+            cli.listen,
+            vec![
+                "127.0.0.1:8080".parse::<SocketAddr>().unwrap(),
+                "127.0.0.1:8081".parse().unwrap()
+            ]
+        );
+        // 最初の = だけで分割する
+        assert_eq!(cli.backends, vec!["http://127.0.0.1:9000/a=b"]);
+        assert_eq!(cli.timeout, Some(10));
+        assert!(matches!(cli.event_format, Some(EventFormat::V1)));
+        assert!(cli.log_format == LogFormat::Json);
+    }
+
+    #[test]
+    fn options_before_and_after_command() {
+        let cli = parse(&["-c", "a.yaml", "validate-config", "--timeout", "5"]).unwrap();
+        assert!(matches!(cli.command, Command::ValidateConfig));
+        assert_eq!(cli.config, Some(PathBuf::from("a.yaml")));
+        assert_eq!(cli.timeout, Some(5));
+
+        let cli = parse(&["serve", "--listen=0.0.0.0:8000"]).unwrap();
+        assert!(matches!(cli.command, Command::Serve));
+        assert_eq!(cli.listen.len(), 1);
+    }
+
+    #[test]
+    fn invoke() {
+        let cli = parse(&["invoke", "-f", "users", "event.json", "-c", "a.yaml"]).unwrap();
+        match cli.command {
+            Command::Invoke {
+                event_file,
+                function,
+            } => {
+                assert_eq!(event_file, PathBuf::from("event.json"));
+                assert_eq!(function.as_deref(), Some("users"));
+            }
+            _ => panic!("expected invoke"),
+        }
+        assert_eq!(cli.config, Some(PathBuf::from("a.yaml")));
+
+        let cli = parse(&["invoke", "-", "--function=admin"]).unwrap();
+        match cli.command {
+            Command::Invoke {
+                event_file,
+                function,
+            } => {
+                assert_eq!(event_file, PathBuf::from("-"));
+                assert_eq!(function.as_deref(), Some("admin"));
+            }
+            _ => panic!("expected invoke"),
+        }
+
+        assert_eq!(parse_err(&["invoke"]), "invoke requires an event file");
+        assert_eq!(
+            parse_err(&["invoke", "a.json", "b.json"]),
+            "unexpected argument: b.json"
+        );
+    }
+
+    #[test]
+    fn function_only_for_invoke() {
+        let cli = parse(&["--function", "users", "invoke", "event.json"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Invoke { function: Some(ref function), .. } if function == "users"
+        ));
+        assert_eq!(
+            parse_err(&["serve", "--function", "users"]),
+            "--function can only be used with invoke"
+        );
+        assert_eq!(
+            parse_err(&["-f", "users"]),
+            "--function can only be used with invoke"
+        );
+    }
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(parse_err(&["--config"]), "--config requires a value");
+        assert_eq!(parse_err(&["serve", "-l"]), "-l requires a value");
+        assert_eq!(
+            parse_err(&["invoke", "event.json", "--function"]),
+            "--function requires a value"
+        );
+    }
+
+    #[test]
+    fn unknown_option_and_command() {
+        assert_eq!(parse_err(&["--verbose"]), "unknown option: --verbose");
+        assert_eq!(parse_err(&["--verbose=1"]), "unknown option: --verbose=1");
+        assert_eq!(parse_err(&["deploy"]), "unknown command: deploy");
+        assert_eq!(parse_err(&["serve", "extra"]), "unexpected argument: extra");
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(
+            parse_err(&["--listen", "localhost"]),
+            "--listen: invalid address: localhost"
+        );
+        assert!(parse_err(&["--event-format", "v3"]).starts_with("--event-format: "));
+        assert_eq!(
+            parse_err(&["--log-format", "xml"]),
+            "--log-format: unknown log format: xml"
+        );
+    }
+
+    #[test]
+    fn timeout_range() {
+        assert_eq!(parse(&["--timeout", "1"]).unwrap().timeout, Some(1));
+        assert_eq!(parse(&["--timeout", "900"]).unwrap().timeout, Some(900));
+        assert_eq!(
+            parse_err(&["--timeout", "0"]),
+            "--timeout: must be between 1 and 900 seconds: 0"
+        );
+        assert_eq!(
+            parse_err(&["--timeout", "901"]),
+            "--timeout: must be between 1 and 900 seconds: 901"
+        );
+        assert_eq!(
+            parse_err(&["--timeout", "abc"]),
+            "--timeout: must be a number of seconds: abc"
+        );
+    }
+
+    #[test]
+    fn help_and_version() {
+        assert!(matches!(parse(&["--help"]).unwrap().command, Command::Help));
+        assert!(matches!(
+            parse(&["invoke", "-h"]).unwrap().command,
+            Command::Help
+        ));
+        assert!(matches!(parse(&["-V"]).unwrap().command, Command::Version));
+    }
+}
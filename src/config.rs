@@ -0,0 +1,586 @@
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::Display,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::event::{ApiContext, EventFormat};
+use crate::function::timeout_from_secs;
+use crate::route::RouteKey;
+use crate::stream::InvokeMode;
+
+/// プロキシの設定。設定ファイル (YAML) か環境変数から作る
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// 待ち受けるアドレス。省略した場合は 0.0.0.0:8000
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    /// 全ての関数に共通の設定。関数ごとの設定で上書きする
+    #[serde(default)]
+    pub defaults: FunctionConfig,
+    #[serde(default)]
+    pub functions: BTreeMap<String, FunctionConfig>,
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// 関数とルートを取り込む SAM テンプレート
+    pub sam_template: Option<PathBuf>,
+    /// X-Forwarded-For を信頼するプロキシの CIDR
+    #[serde(default)]
+    pub trusted_proxies: Vec<Parsed<IpNet>>,
+    /// 関数のエラーの詳細をレスポンスに含める
+    #[serde(default)]
+    pub debug_errors: bool,
+    /// ストリーミングレスポンスのサイズの上限 (バイト)
+    pub streaming_response_limit: Option<usize>,
+    /// バックエンドごとに保持する keep-alive の接続数の上限
+    pub backend_pool_size: Option<usize>,
+    /// http:// のバックエンドにも HTTP/2 (h2c) で接続する。https:// は ALPN で選ぶ
+    #[serde(default)]
+    pub backend_http2: bool,
+    /// コマンドライン引数で指定した値
+    #[serde(skip)]
+    pub overrides: Overrides,
+}
+
+/// 設定ファイル、環境変数、SAM テンプレートのどの値より優先する値
+#[derive(Default)]
+pub struct Overrides {
+    pub timeout: Option<u64>,
+    pub format: Option<EventFormat>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub address: Parsed<SocketAddr>,
+    /// 指定した場合は TLS で待ち受ける
+    pub tls: Option<TlsConfig>,
+}
+
+/// PEM 形式の証明書 (チェーン) と秘密鍵のパス
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+}
+
+/// 関数の設定。省略した項目は defaults の値を使う
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FunctionConfig {
+    /// イベントを POST する URL。複数指定するとそれぞれを 1 つの実行環境とする
+    #[serde(default)]
+    pub backends: Vec<String>,
+    /// プロキシから起動する関数のコマンド
+    pub command: Option<CommandLine>,
+    /// 組み込みの Runtime API のアドレス
+    pub runtime_api: Option<Parsed<SocketAddr>>,
+    /// command で起動するプロセスの環境変数
+    #[serde(default, deserialize_with = "environment")]
+    pub environment: HashMap<String, String>,
+    pub memory_size: Option<u32>,
+    /// タイムアウト (秒)
+    pub timeout: Option<u64>,
+    pub concurrency: Option<u16>,
+    pub reserved_concurrency: Option<usize>,
+    pub format: Option<Parsed<EventFormat>>,
+    pub invoke_mode: Option<Parsed<InvokeMode>>,
+    #[serde(default)]
+    pub context: ContextConfig,
+}
+
+/// ルートごとの設定。format / invoke_mode / context は関数の設定を上書きする
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// ルートキー ("GET /users/{id}") または "$default"
+    pub route: String,
+    pub function: String,
+    pub format: Option<Parsed<EventFormat>>,
+    pub invoke_mode: Option<Parsed<InvokeMode>>,
+    #[serde(default)]
+    pub context: ContextConfig,
+}
+
+/// requestContext の値
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ContextConfig {
+    pub account_id: Option<String>,
+    pub api_id: Option<String>,
+    pub domain_name: Option<String>,
+    pub domain_name_from_host: Option<bool>,
+    pub stage: Option<String>,
+    pub region: Option<String>,
+    pub binary_media_types: Option<Vec<String>>,
+}
+
+/// 関数のコマンド。文字列は実行ファイルのパスとしてそのまま使い、リストは [パス, 引数...] とする
+#[derive(Clone, Debug)]
+pub struct CommandLine(pub Vec<String>);
+
+impl<'de> Deserialize<'de> for CommandLine {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged, expecting = "a path or a list of a path and arguments")]
+        enum Raw {
+            Path(String),
+            List(Vec<String>),
+        }
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Path(path) => CommandLine(vec![path]),
+            Raw::List(args) => CommandLine(args),
+        })
+    }
+}
+
+/// 環境変数の値。SAM テンプレートと同じく数値や真偽値 (PORT: 8080) も文字列にする
+fn environment<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, String>, D::Error> {
+    struct Value(String);
+
+    impl<'de> Deserialize<'de> for Value {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            match serde_json::Value::deserialize(deserializer)? {
+                serde_json::Value::String(s) => Ok(Value(s)),
+                serde_json::Value::Number(n) => Ok(Value(n.to_string())),
+                serde_json::Value::Bool(b) => Ok(Value(b.to_string())),
+                _ => Err(serde::de::Error::custom(
+                    "must be a string, number or boolean",
+                )),
+            }
+        }
+    }
+
+    let values = HashMap::<String, Value>::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .map(|(name, value)| (name, value.0))
+        .collect())
+}
+
+/// FromStr で解釈する文字列の値
+#[derive(Clone, Copy, Debug)]
+pub struct Parsed<T>(pub T);
+
+impl<'de, T> Deserialize<'de> for Parsed<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map(Parsed).map_err(serde::de::Error::custom)
+    }
+}
+
+impl FunctionConfig {
+    /// 省略した項目を defaults で補う
+    pub fn or(&self, defaults: &FunctionConfig) -> FunctionConfig {
+        let mut environment = defaults.environment.clone();
+        environment.extend(self.environment.clone());
+        FunctionConfig {
+            backends: if self.backends.is_empty() {
+                defaults.backends.clone()
+            } else {
+                self.backends.clone()
+            },
+            command: self.command.clone().or(defaults.command.clone()),
+            runtime_api: self.runtime_api.or(defaults.runtime_api),
+            environment,
+            memory_size: self.memory_size.or(defaults.memory_size),
+            timeout: self.timeout.or(defaults.timeout),
+            concurrency: self.concurrency.or(defaults.concurrency),
+            reserved_concurrency: self.reserved_concurrency.or(defaults.reserved_concurrency),
+            format: self.format.or(defaults.format),
+            invoke_mode: self.invoke_mode.or(defaults.invoke_mode),
+            context: self.context.or(&defaults.context),
+        }
+    }
+}
+
+impl ContextConfig {
+    pub fn or(&self, defaults: &ContextConfig) -> ContextConfig {
+        ContextConfig {
+            account_id: self.account_id.clone().or(defaults.account_id.clone()),
+            api_id: self.api_id.clone().or(defaults.api_id.clone()),
+            domain_name: self.domain_name.clone().or(defaults.domain_name.clone()),
+            domain_name_from_host: self
+                .domain_name_from_host
+                .or(defaults.domain_name_from_host),
+            stage: self.stage.clone().or(defaults.stage.clone()),
+            region: self.region.clone().or(defaults.region.clone()),
+            binary_media_types: self
+                .binary_media_types
+                .clone()
+                .or(defaults.binary_media_types.clone()),
+        }
+    }
+
+    pub fn api_context(&self) -> ApiContext {
+        let defaults = ApiContext::default();
+        ApiContext {
+            account_id: self.account_id.clone().unwrap_or(defaults.account_id),
+            api_id: self.api_id.clone().unwrap_or(defaults.api_id),
+            domain_name: self.domain_name.clone(),
+            domain_name_from_host: self.domain_name_from_host.unwrap_or(false),
+            stage: self.stage.clone(),
+            region: self.region.clone().unwrap_or(defaults.region),
+            binary_media_types: self.binary_media_types.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// 設定ファイルを読み込んで検証する。エラーには問題のあるキーを含める
+pub fn load(path: &Path) -> Result<Config, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let value = crate::yaml::parse(&source).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut config: Config = serde_path_to_error::deserialize(value)
+        .map_err(|e| format!("{}: {}: {}", path.display(), e.path(), e.inner()))?;
+    // SAM テンプレートや証明書のパスは設定ファイルからの相対パス
+    let dir = path.parent().unwrap_or(Path::new("."));
+    if let Some(template) = &config.sam_template {
+        config.sam_template = Some(dir.join(template));
+    }
+    for tls in config.listeners.iter_mut().filter_map(|l| l.tls.as_mut()) {
+        tls.certificate = dir.join(&tls.certificate);
+        tls.private_key = dir.join(&tls.private_key);
+    }
+    config
+        .validate()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(config)
+}
+
+impl Config {
+    /// 設定ファイルを使わない場合に、従来の環境変数から同じ設定を組み立てる。
+    /// エラーには問題のある環境変数の名前を含める
+    pub fn from_env() -> Result<Config, String> {
+        let parse = |name: &str| -> Option<String> { env::var(name).ok() };
+        let context = ContextConfig {
+            account_id: parse("ACCOUNT_ID"),
+            api_id: parse("API_ID"),
+            domain_name: parse("DOMAIN_NAME"),
+            domain_name_from_host: parse("DOMAIN_NAME_FROM_HOST").map(|v| v == "1" || v == "true"),
+            stage: parse("STAGE"),
+            region: parse("AWS_REGION"),
+            binary_media_types: parse("BINARY_MEDIA_TYPES").map(|v| {
+                v.split(',')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect()
+            }),
+        };
+        let timeout: Option<u64> = env_parse("TIMEOUT")?;
+        if let Some(timeout) = timeout {
+            timeout_from_secs(timeout).map_err(|e| format!("TIMEOUT: {}", e))?;
+        }
+        let concurrency: Option<u16> = env_parse("CONCURRENCY")?;
+        if concurrency == Some(0) {
+            return Err("CONCURRENCY: must be at least 1".to_string());
+        }
+        let defaults = FunctionConfig {
+            timeout,
+            concurrency,
+            reserved_concurrency: env_parse("RESERVED_CONCURRENCY")?,
+            format: env_parse("EVENT_FORMAT")?.map(Parsed),
+            invoke_mode: env_parse("INVOKE_MODE")?.map(Parsed),
+            context,
+            ..Default::default()
+        };
+
+        let mut functions = BTreeMap::new();
+        let mut routes = Vec::new();
+
+        if parse("FUNCTION_COMMAND").is_some_and(|command| command.trim().is_empty()) {
+            return Err("FUNCTION_COMMAND: must not be empty".to_string());
+        }
+
+        // $default ルートで呼び出す関数
+        if parse("RUNTIME_API").is_some() || parse("FUNCTION_COMMAND").is_some() {
+            let name = parse("FUNCTION_NAME").unwrap_or_else(|| "function".to_string());
+            functions.insert(
+                name.clone(),
+                FunctionConfig {
+                    // FUNCTION_COMMAND は空白区切りのコマンドライン ("./bootstrap --flag")
+                    command: parse("FUNCTION_COMMAND").map(|command| {
+                        CommandLine(command.split_whitespace().map(String::from).collect())
+                    }),
+                    runtime_api: env_parse("RUNTIME_API")?.map(Parsed),
+                    memory_size: env_parse("FUNCTION_MEMORY_SIZE")?,
+                    ..Default::default()
+                },
+            );
+            routes.push(RouteConfig::new("$default", &name));
+        } else if let Some(backend) = parse("BACKEND") {
+            functions.insert(backend.clone(), FunctionConfig::http(&backend));
+            routes.push(RouteConfig::new("$default", &backend));
+        }
+
+        // ROUTES は "GET /users/{id}=http://...;ANY /admin/{proxy+}=http://..." の形式
+        for entry in parse("ROUTES")
+            .unwrap_or_default()
+            .split(';')
+            .filter(|entry| !entry.trim().is_empty())
+        {
+            let (key, backend) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("ROUTES: invalid entry: {}", entry))?;
+            key.trim()
+                .parse::<RouteKey>()
+                .map_err(|e| format!("ROUTES: {}", e))?;
+            let backend = backend.trim();
+            functions
+                .entry(backend.to_string())
+                .or_insert_with(|| FunctionConfig::http(backend));
+            routes.push(RouteConfig::new(key.trim(), backend));
+        }
+
+        // TRUSTED_PROXIES は "10.0.0.0/8,127.0.0.1/32" の形式
+        let trusted_proxies = parse("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|cidr| !cidr.trim().is_empty())
+            .map(|cidr| {
+                cidr.trim()
+                    .parse()
+                    .map(Parsed)
+                    .map_err(|_| format!("TRUSTED_PROXIES: invalid entry: {}", cidr))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let config = Config {
+            listeners: Vec::new(),
+            defaults,
+            functions,
+            routes,
+            sam_template: parse("SAM_TEMPLATE").map(PathBuf::from),
+            trusted_proxies,
+            debug_errors: parse("DEBUG_ERRORS").is_some_and(|v| v == "1" || v == "true"),
+            streaming_response_limit: env_parse("STREAMING_RESPONSE_LIMIT")?,
+            backend_pool_size: env_parse("BACKEND_POOL_SIZE")?,
+            backend_http2: parse("BACKEND_HTTP2").is_some_and(|v| v == "1" || v == "true"),
+            overrides: Overrides::default(),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 値の範囲や参照先を検証する
+    pub fn validate(&self) -> Result<(), String> {
+        // defaults は SAM テンプレートの関数にも使うので、関数と同じく検証する
+        let check_values =
+            |key: &str, config: &FunctionConfig| -> Result<(), String> {
+                if let Some(timeout) = config.timeout {
+                    timeout_from_secs(timeout).map_err(|e| format!("{}.timeout: {}", key, e))?;
+                }
+                if config.command.as_ref().is_some_and(|command| {
+                    command.0.first().is_none_or(|path| path.trim().is_empty())
+                }) {
+                    return Err(format!("{}.command: must not be empty", key));
+                }
+                if config.concurrency == Some(0) {
+                    return Err(format!("{}.concurrency: must be at least 1", key));
+                }
+                Ok(())
+            };
+        check_values("defaults", &self.defaults)?;
+        for (name, function) in &self.functions {
+            let key = format!("functions.{}", name);
+            check_values(&key, function)?;
+            let function = function.or(&self.defaults);
+            if function.backends.is_empty()
+                && function.command.is_none()
+                && function.runtime_api.is_none()
+            {
+                return Err(format!(
+                    "{}: one of backends, command or runtime_api is required",
+                    key
+                ));
+            }
+            if !function.backends.is_empty()
+                && (function.command.is_some() || function.runtime_api.is_some())
+            {
+                return Err(format!(
+                    "{}: backends cannot be combined with command or runtime_api",
+                    key
+                ));
+            }
+        }
+
+        let mut has_default = false;
+        for (i, route) in self.routes.iter().enumerate() {
+            let key = format!("routes[{}]", i);
+            if route.route == "$default" {
+                if has_default {
+                    return Err(format!("{}.route: $default is defined more than once", key));
+                }
+                has_default = true;
+            } else {
+                route
+                    .route
+                    .parse::<RouteKey>()
+                    .map_err(|e| format!("{}.route: {}", key, e))?;
+            }
+            if !self.functions.contains_key(&route.function) {
+                return Err(format!(
+                    "{}.function: unknown function {}",
+                    key, route.function
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 環境変数を FromStr で解釈する。エラーには変数名を含める
+fn env_parse<T>(name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    env::var(name)
+        .ok()
+        .map(|v| v.parse().map_err(|e| format!("{}: {}", name, e)))
+        .transpose()
+}
+
+impl FunctionConfig {
+    /// カンマ区切りの URL をバックエンドとする関数
+    pub fn http(urls: &str) -> FunctionConfig {
+        FunctionConfig {
+            backends: urls.split(',').map(|url| url.trim().to_string()).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl RouteConfig {
+    pub fn new(route: &str, function: &str) -> RouteConfig {
+        RouteConfig {
+            route: route.to_string(),
+            function: function.to_string(),
+            format: None,
+            invoke_mode: None,
+            context: ContextConfig::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_function(source: &str) -> Result<FunctionConfig, String> {
+        let value = crate::yaml::parse(source).map_err(|e| e.to_string())?;
+        serde_path_to_error::deserialize(value).map_err(|e| format!("{}: {}", e.path(), e.inner()))
+    }
+
+    #[test]
+    fn command_path_is_not_split() {
+        let function = parse_function("command: /opt/my functions/bootstrap\n").unwrap();
+        assert_eq!(
+            function.command.unwrap().0,
+            vec!["/opt/my functions/bootstrap"]
+        );
+    }
+
+    #[test]
+    fn command_list_has_arguments() {
+        let function = parse_function("command: [./bootstrap, --flag, \"a b\"]\n").unwrap();
+        assert_eq!(
+            function.command.unwrap().0,
+            vec!["./bootstrap", "--flag", "a b"]
+        );
+
+        let function = parse_function("command:\n  - ./bootstrap\n  - --flag\n").unwrap();
+        assert_eq!(function.command.unwrap().0, vec!["./bootstrap", "--flag"]);
+    }
+
+    #[test]
+    fn command_must_be_string_or_list() {
+        let e = parse_function("command: {path: ./bootstrap}\n")
+            .err()
+            .unwrap();
+        assert!(e.starts_with("command: "), "{}", e);
+    }
+
+    #[test]
+    fn environment_values_are_strings() {
+        let function = parse_function(
+            "environment:\n  TABLE: users\n  PORT: 8080\n  RATIO: 0.5\n  DEBUG: true\n  QUOTED: \"8080\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            function.environment,
+            HashMap::from([
+                ("TABLE".to_string(), "users".to_string()),
+                ("PORT".to_string(), "8080".to_string()),
+                ("RATIO".to_string(), "0.5".to_string()),
+                ("DEBUG".to_string(), "true".to_string()),
+                ("QUOTED".to_string(), "8080".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn environment_rejects_other_values() {
+        for value in ["", "[a, b]", "{a: b}"] {
+            let e = parse_function(&format!("environment:\n  PORT: {}\n", value))
+                .err()
+                .unwrap();
+            assert_eq!(
+                e, "environment.PORT: must be a string, number or boolean",
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn empty_command_is_rejected() {
+        for command in ["\"\"", "[]", "[\" \", --flag]"] {
+            let config = Config {
+                functions: BTreeMap::from([(
+                    "app".to_string(),
+                    parse_function(&format!("command: {}\n", command)).unwrap(),
+                )]),
+                ..Default::default()
+            };
+            assert_eq!(
+                config.validate().err().as_deref(),
+                Some("functions.app.command: must not be empty"),
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn defaults_are_validated() {
+        for (defaults, error) in [
+            ("concurrency: 0", "defaults.concurrency: must be at least 1"),
+            ("command: \"\"", "defaults.command: must not be empty"),
+            (
+                "timeout: 0",
+                "defaults.timeout: must be between 1 and 900 seconds",
+            ),
+        ] {
+            let config = Config {
+                defaults: parse_function(defaults).unwrap(),
+                sam_template: Some(PathBuf::from("template.yaml")),
+                ..Default::default()
+            };
+            assert_eq!(config.validate().err().as_deref(), Some(error));
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use std::time::Duration;
+
+/// 関数の呼び出しに失敗したときのエラー。Function URL が返すものと同じステータスとボディに変換する
+#[derive(Debug)]
+pub enum ProxyError {
+    /// バックエンドに接続できない、またはバックエンドが 5xx を返した (Lambda サービス側の障害に相当)
+    Backend(String),
+    /// 関数のレスポンスが不正 (JSON でない、base64 のデコードに失敗した、ヘッダーが不正など)
+    InvalidResponse(String),
+    /// 関数がエラーを返した ({"errorMessage", "errorType", "stackTrace"})
+    Function {
+        payload: serde_json::Value,
+        /// エラーの詳細をレスポンスとログに含める
+        debug: bool,
+    },
+    /// クライアントからのリクエストのボディを読み込めなかった
+    RequestBody(String),
+    /// イベントのサイズが上限を超えた
+    RequestTooLarge(usize),
+    /// 関数がタイムアウトした
+    Timeout(Duration),
+    /// 予約された同時実行数を超えた
+    TooManyRequests,
+}
+
+impl ProxyError {
+    /// レスポンスのボディの読み込みエラー。タイムアウトで打ち切られた場合は Timeout にする
+    pub fn from_body_error(error: axum::Error) -> Self {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+        while let Some(e) = source {
+            if let Some(TimedOut(timeout)) = e.downcast_ref::<TimedOut>() {
+                return ProxyError::Timeout(*timeout);
+            }
+            source = e.source();
+        }
+        ProxyError::Backend(error.to_string())
+    }
+}
+
+/// タイムアウトしたためにレスポンスのボディを打ち切った
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Task timed out after {:.2} seconds",
+            self.0.as_secs_f64()
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::Backend(message) => write!(f, "backend error: {}", message),
+            ProxyError::InvalidResponse(message) => {
+                write!(f, "invalid function response: {}", message)
+            }
+            ProxyError::Function { payload, .. } => write!(
+                f,
+                "function error: {}: {}",
+                payload["errorType"].as_str().unwrap_or("Unhandled"),
+                payload["errorMessage"].as_str().unwrap_or_default()
+            ),
+            ProxyError::RequestBody(message) => {
+                write!(f, "failed to read request body: {}", message)
+            }
+            ProxyError::RequestTooLarge(limit) => write!(
+                f,
+                "Request must be smaller than {} bytes for the InvokeFunction operation",
+                limit
+            ),
+            ProxyError::Timeout(timeout) => write!(f, "{}", TimedOut(*timeout)),
+            ProxyError::TooManyRequests => write!(f, "too many requests"),
+        }
+    }
+}
+
+impl IntoResponse for ProxyError {
+    fn into_response(self) -> Response {
+        match self {
+            ProxyError::Backend(_) | ProxyError::RequestBody(_) => {
+                tracing::error!("{}", self);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    [("x-amzn-ErrorType", "ServiceException")],
+                    "Internal Server Error",
+                )
+                    .into_response()
+            }
+            ProxyError::InvalidResponse(_) | ProxyError::Timeout(_) => {
+                // タイムアウトはプールがログに出力している
+                if !matches!(self, ProxyError::Timeout(_)) {
+                    tracing::error!("{}", self);
+                }
+                (
+                    StatusCode::BAD_GATEWAY,
+                    [("x-amzn-ErrorType", "InternalServerErrorException")],
+                    "Internal Server Error",
+                )
+                    .into_response()
+            }
+            ProxyError::Function { ref payload, debug } => {
+                if !debug {
+                    tracing::error!("{}", self);
+                    return (
+                        StatusCode::BAD_GATEWAY,
+                        [("x-amzn-ErrorType", "InternalServerErrorException")],
+                        "Internal Server Error",
+                    )
+                        .into_response();
+                }
+                tracing::error!("{}\n{}", self, stack_trace(payload));
+                (
+                    StatusCode::BAD_GATEWAY,
+                    [("x-amzn-ErrorType", "InternalServerErrorException")],
+                    Json(payload.clone()),
+                )
+                    .into_response()
+            }
+            ProxyError::RequestTooLarge(_) => {
+                tracing::warn!("{}", self);
+                (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    [("x-amzn-ErrorType", "RequestTooLargeException")],
+                    Json(serde_json::json!({
+                        "Type": "User",
+                        "message": self.to_string(),
+                    })),
+                )
+                    .into_response()
+            }
+            ProxyError::TooManyRequests => {
+                tracing::warn!("{}", self);
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [("x-amzn-ErrorType", "TooManyRequestsException")],
+                    Json(serde_json::json!({
+                        "Reason": "ReservedFunctionConcurrentInvocationLimitExceeded",
+                        "Type": "User",
+                        "message": "Rate Exceeded.",
+                    })),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// stackTrace は文字列の配列 (Python / Node.js) か文字列
+fn stack_trace(payload: &serde_json::Value) -> String {
+    match &payload["stackTrace"] {
+        serde_json::Value::Array(lines) => lines
+            .iter()
+            .map(|line| line.as_str().map(String::from).unwrap_or(line.to_string()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        serde_json::Value::String(s) => s.clone(),
+        _ => String::new(),
+    }
+}
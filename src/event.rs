@@ -0,0 +1,753 @@
+use axum::http::{HeaderMap, Method, Version, header};
+use base64::Engine;
+use base64::engine::general_purpose;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde_json::{Value, json};
+use std::{collections::HashMap, net::IpAddr, str::FromStr, sync::Arc};
+
+use crate::route::RouteKey;
+
+/// Lambda に渡すイベントの形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventFormat {
+    /// Function URL / HTTP API (payload format 2.0)
+    V2,
+    /// API Gateway REST API (payload format 1.0)
+    V1,
+    /// Application Load Balancer のターゲットグループ
+    Alb { multi_value_headers: bool },
+}
+
+impl FromStr for EventFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v2" | "2.0" => Ok(EventFormat::V2),
+            "v1" | "1.0" => Ok(EventFormat::V1),
+            "alb" => Ok(EventFormat::Alb {
+                multi_value_headers: false,
+            }),
+            "alb-multi-value" => Ok(EventFormat::Alb {
+                multi_value_headers: true,
+            }),
+            _ => Err(format!("unknown event format: {}", s)),
+        }
+    }
+}
+
+/// requestContext に入れる API の情報
+#[derive(Clone, Debug)]
+pub struct ApiContext {
+    pub account_id: String,
+    pub api_id: String,
+    /// None の場合は apiId とリージョンから組み立てる
+    pub domain_name: Option<String>,
+    /// リクエストの Host ヘッダーを domainName にする
+    pub domain_name_from_host: bool,
+    /// None の場合は $default (payload format 2.0) / Prod (1.0)
+    pub stage: Option<String>,
+    pub region: String,
+    /// REST API (payload format 1.0) で base64 エンコードする Content-Type ("image/*" などのワイルドカード可)
+    pub binary_media_types: Vec<String>,
+}
+
+impl Default for ApiContext {
+    fn default() -> Self {
+        ApiContext {
+            account_id: "anonymous".to_string(),
+            api_id: "xxxxxxxxxx".to_string(),
+            domain_name: None,
+            domain_name_from_host: false,
+            stage: None,
+            region: "ap-northeast-1".to_string(),
+            binary_media_types: Vec::new(),
+        }
+    }
+}
+
+impl ApiContext {
+    /// Runtime API の Lambda-Runtime-Invoked-Function-Arn に渡す関数の ARN
+    pub fn function_arn(&self, function_name: &str) -> String {
+        format!(
+            "arn:aws:lambda:{}:{}:function:{}",
+            self.region, self.account_id, function_name
+        )
+    }
+}
+
+/// イベントの組み立てに必要な HTTP リクエストの情報
+pub struct RequestParts {
+    pub method: Method,
+    /// HTTP/1.0, HTTP/1.1, HTTP/2.0
+    pub version: Version,
+    pub path: String,
+    pub query_string: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub time: DateTime<Utc>,
+    pub request_id: String,
+    /// クライアントの IP アドレス
+    pub source_ip: IpAddr,
+    pub api: Arc<ApiContext>,
+    /// マッチしたルート。$default の場合は None
+    pub route_key: Option<RouteKey>,
+    pub path_parameters: HashMap<String, String>,
+}
+
+impl RequestParts {
+    fn header_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+    }
+
+    /// domainName と domainPrefix。default_suffix は apiId に続けるドメイン
+    fn domain_name(&self, default_suffix: &str) -> (String, String) {
+        let host = self
+            .header_values(header::HOST.as_str())
+            .next()
+            .filter(|_| self.api.domain_name_from_host)
+            .map(|host| match host.rsplit_once(':') {
+                // IPv6 アドレスの ':' はポートの区切りとみなさない
+                Some((name, port)) if !name.ends_with(':') && port.parse::<u16>().is_ok() => {
+                    name.to_string()
+                }
+                _ => host,
+            });
+        let domain_name = host
+            .or_else(|| self.api.domain_name.clone())
+            .unwrap_or_else(|| {
+                format!(
+                    "{}.{}",
+                    self.api.api_id,
+                    default_suffix.replace("{region}", &self.api.region)
+                )
+            });
+        let domain_prefix = domain_name
+            .split('.')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        (domain_name, domain_prefix)
+    }
+
+    /// パラメーターを除いた小文字の Content-Type
+    fn content_type(&self) -> String {
+        self.header_values(header::CONTENT_TYPE.as_str())
+            .next()
+            .unwrap_or_default()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    }
+
+    /// Function URL / HTTP API / ALB がテキストとして渡す Content-Type で、UTF-8 として読めるか
+    fn is_text_body(&self) -> bool {
+        let content_type = self.content_type();
+        let text = content_type.starts_with("text/")
+            || content_type.ends_with("+json")
+            || content_type.ends_with("+xml")
+            || matches!(
+                content_type.as_str(),
+                "application/json"
+                    | "application/javascript"
+                    | "application/xml"
+                    | "application/yaml"
+                    | "application/x-www-form-urlencoded"
+            );
+        text && std::str::from_utf8(&self.body).is_ok()
+    }
+
+    /// REST API の binaryMediaTypes にマッチするか
+    fn is_binary_media_type(&self) -> bool {
+        let content_type = self.content_type();
+        self.api.binary_media_types.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.split_once('/') {
+                Some(("*", "*")) => true,
+                Some((kind, "*")) => content_type.split('/').next() == Some(kind),
+                _ => content_type == pattern,
+            }
+        })
+    }
+
+    /// ボディと isBase64Encoded
+    fn encode_body(&self, binary: bool) -> (String, bool) {
+        if binary {
+            (general_purpose::STANDARD.encode(&self.body), true)
+        } else {
+            (String::from_utf8_lossy(&self.body).into_owned(), false)
+        }
+    }
+
+    fn user_agent(&self) -> String {
+        self.header_values(header::USER_AGENT.as_str())
+            .next()
+            .unwrap_or_default()
+    }
+
+    /// 同じ名前のヘッダーが複数ある場合はカンマ区切りで結合する (HTTP API)
+    fn joined_headers(&self) -> HashMap<String, String> {
+        self.headers
+            .keys()
+            .map(|name| {
+                (
+                    name.to_string(),
+                    self.header_values(name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                )
+            })
+            .collect()
+    }
+
+    /// 同じ名前のヘッダーが複数ある場合は最後の値 (REST API / ALB)
+    fn single_value_headers(&self) -> HashMap<String, String> {
+        self.headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect()
+    }
+
+    fn multi_value_headers(&self) -> HashMap<String, Vec<String>> {
+        self.headers
+            .keys()
+            .map(|name| {
+                (
+                    name.to_string(),
+                    self.header_values(name.as_str()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// 同じキーが複数ある場合は最後の値
+    fn query(&self) -> HashMap<String, String> {
+        url::form_urlencoded::parse(self.query_string.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    fn multi_value_query(&self) -> HashMap<String, Vec<String>> {
+        let mut query: HashMap<String, Vec<String>> = HashMap::new();
+        url::form_urlencoded::parse(self.query_string.as_bytes())
+            .into_owned()
+            .for_each(|(k, v)| query.entry(k).or_default().push(v));
+        query
+    }
+
+    /// ALB はクエリ文字列をデコードせずにそのまま渡す
+    fn raw_query(&self) -> Vec<(String, String)> {
+        self.query_string
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((k, v)) => (k.to_string(), v.to_string()),
+                None => (pair.to_string(), String::new()),
+            })
+            .collect()
+    }
+}
+
+/// X-Ray のトレース ID (Root=1-{時刻}-{乱数};Sampled=0)
+pub fn new_trace_id() -> String {
+    let random: [u8; 12] = rand::rng().random();
+    let random: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "Root=1-{:08x}-{};Sampled=0",
+        chrono::Utc::now().timestamp(),
+        random
+    )
+}
+
+/// UUID v4 形式のリクエスト ID
+pub fn new_request_id() -> String {
+    let mut bytes: [u8; 16] = rand::rng().random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// requestContext の protocol ("HTTP/1.1" など)
+fn protocol(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2.0",
+        Version::HTTP_3 => "HTTP/3.0",
+        _ => "HTTP/1.1",
+    }
+}
+
+pub fn build_event(format: EventFormat, req: &RequestParts) -> Value {
+    match format {
+        EventFormat::V2 => build_v2(req),
+        EventFormat::V1 => build_v1(req),
+        EventFormat::Alb {
+            multi_value_headers,
+        } => build_alb(req, multi_value_headers),
+    }
+}
+
+fn build_v2(req: &RequestParts) -> Value {
+    // Function URL と同様に Cookie ヘッダーは cookies に分割し、headers からは取り除く
+    let cookies: Vec<String> = req
+        .header_values(header::COOKIE.as_str())
+        .flat_map(|value| {
+            value
+                .split(';')
+                .map(|cookie| cookie.trim().to_string())
+                .collect::<Vec<_>>()
+        })
+        .filter(|cookie| !cookie.is_empty())
+        .collect();
+
+    let mut headers = req.joined_headers();
+    headers.remove(header::COOKIE.as_str());
+
+    let route_key = req
+        .route_key
+        .as_ref()
+        .map(|key| key.to_string())
+        .unwrap_or_else(|| "$default".to_string());
+
+    let (domain_name, domain_prefix) = req.domain_name("lambda-url.{region}.on.aws");
+
+    let mut event = json!({
+      "version": "2.0",
+      "routeKey": route_key,
+      "rawPath": req.path,
+      "rawQueryString": req.query_string,
+      "headers": headers,
+      "requestContext": {
+        "accountId": req.api.account_id,
+        "apiId": req.api.api_id,
+        "domainName": domain_name,
+        "domainPrefix": domain_prefix,
+        "http": {
+          "method": req.method.as_str(),
+          "path": req.path,
+          "protocol": protocol(req.version),
+          "sourceIp": req.source_ip.to_string(),
+          "userAgent": req.user_agent()
+        },
+        "requestId": req.request_id,
+        "routeKey": route_key,
+        "stage": req.api.stage.as_deref().unwrap_or("$default"),
+        "time": req.time.format("%d/%b/%Y:%H:%M:%S %z").to_string(),
+        "timeEpoch": req.time.timestamp_millis(),
+      },
+      "isBase64Encoded": false
+    });
+    // ボディがなければ省略する
+    if !req.body.is_empty() {
+        let (body, is_base64_encoded) = req.encode_body(!req.is_text_body());
+        event["body"] = json!(body);
+        event["isBase64Encoded"] = json!(is_base64_encoded);
+    }
+    if !cookies.is_empty() {
+        event["cookies"] = json!(cookies);
+    }
+    // 同じキーが複数ある場合はカンマ区切りで結合する。クエリがなければ省略する
+    let query: HashMap<String, String> = req
+        .multi_value_query()
+        .into_iter()
+        .map(|(k, values)| (k, values.join(",")))
+        .collect();
+    if !query.is_empty() {
+        event["queryStringParameters"] = json!(query);
+    }
+    if !req.path_parameters.is_empty() {
+        event["pathParameters"] = json!(req.path_parameters);
+    }
+    event
+}
+
+fn build_v1(req: &RequestParts) -> Value {
+    let query = req.query();
+    let (query, multi_value_query) = if query.is_empty() {
+        (Value::Null, Value::Null)
+    } else {
+        (json!(query), json!(req.multi_value_query()))
+    };
+    let (resource, path_parameters) = match &req.route_key {
+        Some(key) if req.path_parameters.is_empty() => (key.resource(), Value::Null),
+        Some(key) => (key.resource(), json!(req.path_parameters)),
+        // ルートパスは {proxy+} にマッチしない
+        None if req.path == "/" => ("/".to_string(), Value::Null),
+        None => (
+            "/{proxy+}".to_string(),
+            json!({ "proxy": req.path.trim_start_matches('/') }),
+        ),
+    };
+
+    // binaryMediaTypes にマッチしなければテキストとして渡す。ボディがなければ null
+    let (body, is_base64_encoded) = if req.body.is_empty() {
+        (Value::Null, false)
+    } else {
+        let (body, is_base64_encoded) = req.encode_body(req.is_binary_media_type());
+        (json!(body), is_base64_encoded)
+    };
+    let (domain_name, domain_prefix) = req.domain_name("execute-api.{region}.amazonaws.com");
+
+    json!({
+      "version": "1.0",
+      "resource": resource,
+      "path": req.path,
+      "httpMethod": req.method.as_str(),
+      "headers": req.single_value_headers(),
+      "multiValueHeaders": req.multi_value_headers(),
+      "queryStringParameters": query,
+      "multiValueQueryStringParameters": multi_value_query,
+      "requestContext": {
+        "accountId": req.api.account_id,
+        "apiId": req.api.api_id,
+        "domainName": domain_name,
+        "domainPrefix": domain_prefix,
+        "extendedRequestId": "xxxxxxxxxxxxxxxx",
+        "httpMethod": req.method.as_str(),
+        "identity": {
+          "accessKey": null,
+          "accountId": null,
+          "caller": null,
+          "cognitoAuthenticationProvider": null,
+          "cognitoAuthenticationType": null,
+          "cognitoIdentityId": null,
+          "cognitoIdentityPoolId": null,
+          "principalOrgId": null,
+          "sourceIp": req.source_ip.to_string(),
+          "user": null,
+          "userAgent": req.user_agent(),
+          "userArn": null
+        },
+        "path": req.path,
+        "protocol": protocol(req.version),
+        "requestId": req.request_id,
+        "requestTime": req.time.format("%d/%b/%Y:%H:%M:%S %z").to_string(),
+        "requestTimeEpoch": req.time.timestamp_millis(),
+        "resourceId": "xxxxxx",
+        "resourcePath": resource,
+        "stage": req.api.stage.as_deref().unwrap_or("Prod")
+      },
+      "pathParameters": path_parameters,
+      "stageVariables": null,
+      "body": body,
+      "isBase64Encoded": is_base64_encoded
+    })
+}
+
+fn build_alb(req: &RequestParts, multi_value_headers: bool) -> Value {
+    let (body, is_base64_encoded) = req.encode_body(!req.body.is_empty() && !req.is_text_body());
+
+    let mut event = json!({
+      "requestContext": {
+        "elb": {
+          "targetGroupArn": format!(
+            "arn:aws:elasticloadbalancing:{}:{}:targetgroup/xxxxxxxxxx/xxxxxxxxxxxxxxxx",
+            req.api.region, req.api.account_id
+          )
+        }
+      },
+      "httpMethod": req.method.as_str(),
+      "path": req.path,
+      "body": body,
+      "isBase64Encoded": is_base64_encoded
+    });
+    if multi_value_headers {
+        let mut query: HashMap<String, Vec<String>> = HashMap::new();
+        req.raw_query()
+            .into_iter()
+            .for_each(|(k, v)| query.entry(k).or_default().push(v));
+        event["multiValueQueryStringParameters"] = json!(query);
+        event["multiValueHeaders"] = json!(req.multi_value_headers());
+    } else {
+        let query: HashMap<_, _> = req.raw_query().into_iter().collect();
+        event["queryStringParameters"] = json!(query);
+        event["headers"] = json!(req.single_value_headers());
+    }
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, path: &str, query_string: &str) -> RequestParts {
+        RequestParts {
+            method,
+            version: Version::HTTP_11,
+            path: path.to_string(),
+            query_string: query_string.to_string(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            time: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            request_id: "request-id".to_string(),
+            source_ip: "203.0.113.1".parse().unwrap(),
+            api: Arc::new(ApiContext::default()),
+            route_key: None,
+            path_parameters: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn alb_target_group_arn_from_context() {
+        let mut req = request(Method::GET, "/", "");
+        req.api = Arc::new(ApiContext {
+            region: "us-west-2".to_string(),
+            account_id: "111122223333".to_string(),
+            ..Default::default()
+        });
+        let event = build_alb(&req, false);
+        assert_eq!(
+            event["requestContext"]["elb"]["targetGroupArn"],
+            "arn:aws:elasticloadbalancing:us-west-2:111122223333:targetgroup/xxxxxxxxxx/xxxxxxxxxxxxxxxx"
+        );
+    }
+
+    #[test]
+    fn protocol_names() {
+        assert_eq!(protocol(Version::HTTP_10), "HTTP/1.0");
+        assert_eq!(protocol(Version::HTTP_11), "HTTP/1.1");
+        assert_eq!(protocol(Version::HTTP_2), "HTTP/2.0");
+    }
+
+    fn with_headers(mut req: RequestParts, headers: &[(&str, &[u8])]) -> RequestParts {
+        for (name, value) in headers {
+            req.headers.append(
+                header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                header::HeaderValue::from_bytes(value).unwrap(),
+            );
+        }
+        req
+    }
+
+    fn with_body(mut req: RequestParts, content_type: &str, body: &[u8]) -> RequestParts {
+        req.headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_str(content_type).unwrap(),
+        );
+        req.body = body.to_vec();
+        req
+    }
+
+    #[test]
+    fn v2_query_joins_repeated_keys() {
+        let event = build_v2(&request(Method::GET, "/", "a=1&a=2&b=x%20y"));
+        assert_eq!(event["rawQueryString"], "a=1&a=2&b=x%20y");
+        assert_eq!(
+            event["queryStringParameters"],
+            json!({ "a": "1,2", "b": "x y" })
+        );
+    }
+
+    #[test]
+    fn v2_omits_empty_query_body_and_cookies() {
+        let event = build_v2(&request(Method::GET, "/", ""));
+        let event = event.as_object().unwrap();
+        assert_eq!(event["rawQueryString"], "");
+        assert!(!event.contains_key("queryStringParameters"));
+        assert!(!event.contains_key("body"));
+        assert!(!event.contains_key("cookies"));
+        assert!(!event.contains_key("pathParameters"));
+        assert_eq!(event["isBase64Encoded"], false);
+    }
+
+    #[test]
+    fn v2_splits_cookies_out_of_headers() {
+        let req = with_headers(
+            request(Method::GET, "/", ""),
+            &[("cookie", b"a=1; b=2"), ("cookie", b"c=3"), ("x-a", b"v")],
+        );
+        let event = build_v2(&req);
+        assert_eq!(event["cookies"], json!(["a=1", "b=2", "c=3"]));
+        assert_eq!(event["headers"], json!({ "x-a": "v" }));
+    }
+
+    #[test]
+    fn v2_joins_repeated_headers() {
+        let req = with_headers(
+            request(Method::GET, "/", ""),
+            &[("x-a", b"1"), ("x-a", b"2"), ("x-b", b"caf\xe9")],
+        );
+        let event = build_v2(&req);
+        assert_eq!(event["headers"]["x-a"], "1,2");
+        // UTF-8 でない値は置換文字にする
+        assert_eq!(event["headers"]["x-b"], "caf\u{fffd}");
+    }
+
+    #[test]
+    fn v2_body_text_or_base64() {
+        let event = build_v2(&with_body(
+            request(Method::POST, "/", ""),
+            "application/json; charset=utf-8",
+            b"{\"a\":1}",
+        ));
+        assert_eq!(event["body"], "{\"a\":1}");
+        assert_eq!(event["isBase64Encoded"], false);
+
+        let event = build_v2(&with_body(
+            request(Method::POST, "/", ""),
+            "image/png",
+            b"\x89PNG",
+        ));
+        assert_eq!(event["body"], "iVBORw==");
+        assert_eq!(event["isBase64Encoded"], true);
+
+        // テキストの Content-Type でも UTF-8 として読めなければ base64
+        let event = build_v2(&with_body(
+            request(Method::POST, "/", ""),
+            "text/plain",
+            b"\xff",
+        ));
+        assert_eq!(event["body"], "/w==");
+        assert_eq!(event["isBase64Encoded"], true);
+    }
+
+    #[test]
+    fn v1_query_and_multi_value_query() {
+        let event = build_v1(&request(Method::GET, "/", "a=1&a=2&b=x%20y"));
+        assert_eq!(
+            event["queryStringParameters"],
+            json!({ "a": "2", "b": "x y" })
+        );
+        assert_eq!(
+            event["multiValueQueryStringParameters"],
+            json!({ "a": ["1", "2"], "b": ["x y"] })
+        );
+
+        let event = build_v1(&request(Method::GET, "/", ""));
+        assert_eq!(event["queryStringParameters"], Value::Null);
+        assert_eq!(event["multiValueQueryStringParameters"], Value::Null);
+        assert_eq!(event["body"], Value::Null);
+        assert_eq!(event["isBase64Encoded"], false);
+    }
+
+    #[test]
+    fn v1_headers_and_multi_value_headers() {
+        let req = with_headers(
+            request(Method::GET, "/", ""),
+            &[("x-a", b"1"), ("x-a", b"2"), ("cookie", b"a=1")],
+        );
+        let event = build_v1(&req);
+        assert_eq!(event["headers"], json!({ "x-a": "2", "cookie": "a=1" }));
+        assert_eq!(
+            event["multiValueHeaders"],
+            json!({ "x-a": ["1", "2"], "cookie": ["a=1"] })
+        );
+    }
+
+    #[test]
+    fn v1_binary_media_types() {
+        let body = b"\x89PNG";
+        // binaryMediaTypes にマッチしなければテキストとして渡す
+        let event = build_v1(&with_body(
+            request(Method::POST, "/", ""),
+            "image/png",
+            body,
+        ));
+        assert_eq!(event["isBase64Encoded"], false);
+
+        for (pattern, content_type, binary) in [
+            ("image/*", "image/png", true),
+            ("image/*", "text/plain", false),
+            ("*/*", "text/plain", true),
+            ("application/octet-stream", "application/octet-stream", true),
+            (
+                "Application/Octet-Stream",
+                "application/octet-stream; x=1",
+                true,
+            ),
+            ("application/octet-stream", "application/pdf", false),
+        ] {
+            let mut req = with_body(request(Method::POST, "/", ""), content_type, body);
+            req.api = Arc::new(ApiContext {
+                binary_media_types: vec![pattern.to_string()],
+                ..Default::default()
+            });
+            let event = build_v1(&req);
+            assert_eq!(
+                event["isBase64Encoded"], binary,
+                "{} {}",
+                pattern, content_type
+            );
+            if binary {
+                assert_eq!(event["body"], "iVBORw==");
+            }
+        }
+    }
+
+    #[test]
+    fn v1_proxy_resource_for_default_route() {
+        let event = build_v1(&request(Method::GET, "/a/b", ""));
+        assert_eq!(event["resource"], "/{proxy+}");
+        assert_eq!(event["pathParameters"], json!({ "proxy": "a/b" }));
+
+        let event = build_v1(&request(Method::GET, "/", ""));
+        assert_eq!(event["resource"], "/");
+        assert_eq!(event["pathParameters"], Value::Null);
+    }
+
+    #[test]
+    fn alb_keeps_query_encoded() {
+        let event = build_alb(&request(Method::GET, "/", "a=%20b&c&a=d+e"), false);
+        assert_eq!(
+            event["queryStringParameters"],
+            json!({ "a": "d+e", "c": "" })
+        );
+
+        let event = build_alb(&request(Method::GET, "/", "a=%20b&c&a=d+e"), true);
+        assert_eq!(
+            event["multiValueQueryStringParameters"],
+            json!({ "a": ["%20b", "d+e"], "c": [""] })
+        );
+        assert!(event.get("queryStringParameters").is_none());
+    }
+
+    #[test]
+    fn alb_headers() {
+        let req = with_headers(
+            request(Method::GET, "/", ""),
+            &[("x-a", b"1"), ("x-a", b"2")],
+        );
+        assert_eq!(build_alb(&req, false)["headers"], json!({ "x-a": "2" }));
+        let event = build_alb(&req, true);
+        assert_eq!(event["multiValueHeaders"], json!({ "x-a": ["1", "2"] }));
+        assert!(event.get("headers").is_none());
+    }
+
+    #[test]
+    fn alb_body() {
+        let event = build_alb(&request(Method::GET, "/", ""), false);
+        assert_eq!(event["body"], "");
+        assert_eq!(event["isBase64Encoded"], false);
+
+        let event = build_alb(
+            &with_body(
+                request(Method::POST, "/", ""),
+                "application/octet-stream",
+                b"\x89PNG",
+            ),
+            false,
+        );
+        assert_eq!(event["body"], "iVBORw==");
+        assert_eq!(event["isBase64Encoded"], true);
+    }
+}
@@ -0,0 +1,38 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::event::{ApiContext, EventFormat};
+use crate::pool::Pool;
+use crate::stream::InvokeMode;
+
+/// プロキシから呼び出す Lambda 関数
+pub struct Function {
+    pub name: String,
+    pub pool: Arc<Pool>,
+    pub format: EventFormat,
+    pub invoke_mode: InvokeMode,
+    pub timeout: Duration,
+    /// requestContext に入れる API の情報
+    pub api: Arc<ApiContext>,
+}
+
+/// Lambda のタイムアウトのデフォルトと上限
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(900);
+
+/// Lambda と同じく 1 秒から 900 秒まで
+pub fn timeout_from_secs(seconds: u64) -> Result<Duration, String> {
+    let timeout = Duration::from_secs(seconds);
+    if timeout.is_zero() || timeout > MAX_TIMEOUT {
+        return Err(format!(
+            "must be between 1 and {} seconds",
+            MAX_TIMEOUT.as_secs()
+        ));
+    }
+    Ok(timeout)
+}
+
+/// 同期呼び出しのリクエストとレスポンスのペイロードの上限 (6 MB)
+pub const MAX_PAYLOAD_SIZE: usize = 6 * 1024 * 1024;
+
+/// ストリーミングレスポンスのデフォルトの上限 (20 MB)
+pub const DEFAULT_STREAMING_RESPONSE_LIMIT: usize = 20 * 1024 * 1024;
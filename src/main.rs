@@ -1,186 +1,892 @@
+mod backend;
+mod cli;
+mod config;
+mod error;
+mod event;
+mod function;
+mod logging;
+mod pool;
+mod process;
+mod response;
+mod route;
+mod runtime_api;
+mod sam;
+mod stream;
+mod tls;
+mod yaml;
+
 use axum::{
-    Router,
-    extract::Request,
-    http::{HeaderName, Response, StatusCode},
+    Json, Router,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::IntoResponse,
 };
-use base64::Engine;
-use base64::engine::general_purpose;
-use chrono::{DateTime, Utc};
-use serde::Deserialize;
-use std::{collections::HashMap, env, net::SocketAddr};
+use backend::Backend;
+use chrono::Utc;
+use cli::{Cli, Command};
+use config::{Config, FunctionConfig};
+use error::ProxyError;
+use event::{ApiContext, EventFormat, RequestParts};
+use function::{
+    DEFAULT_STREAMING_RESPONSE_LIMIT, DEFAULT_TIMEOUT, Function, MAX_PAYLOAD_SIZE,
+    timeout_from_secs,
+};
+use ipnet::IpNet;
+use pool::Pool;
+use process::FunctionProcess;
+use response::LambdaResponse;
+use route::{Route, RouteKey, RouteTable};
+use runtime_api::RuntimeApi;
+use std::{
+    collections::HashMap,
+    env,
+    io::{Read, Write},
+    net::IpAddr,
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use stream::InvokeMode;
 use tokio::signal;
 use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
 use tower::ServiceBuilder;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct LambdaResponse {
-    status_code: u16,
-    headers: HashMap<String, String>,
-    body: String,
-    is_base64_encoded: Option<bool>,
-}
-
-impl LambdaResponse {
-    fn body(&self) -> Vec<u8> {
-        if self.is_base64_encoded.unwrap_or(false) {
-            general_purpose::STANDARD.decode(self.body.clone()).unwrap()
-        } else {
-            self.body.clone().into_bytes()
-        }
+use tracing::Instrument;
+
+#[derive(Clone)]
+struct AppState {
+    routes: Arc<RouteTable>,
+    /// 関数のエラーの詳細をレスポンスに含める
+    debug_errors: bool,
+    /// ストリーミングレスポンスのサイズの上限
+    streaming_response_limit: usize,
+    /// X-Forwarded-For を信頼するプロキシのアドレス
+    trusted_proxies: Arc<Vec<IpNet>>,
+    /// X-Forwarded-Port に入れる待ち受けポート
+    port: u16,
+    /// X-Forwarded-Proto に入れるスキーム
+    scheme: &'static str,
+}
+
+/// リクエスト ID を発行し、ログとレスポンスヘッダーに付けて handle_request を呼ぶ
+async fn handle_all(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+) -> axum::response::Response {
+    let request_id = event::new_request_id();
+    let span = tracing::info_span!("invoke", request_id = %request_id);
+    // エラーは into_response でログに出力するので、それもスパンの中で呼ぶ
+    let mut response = async {
+        handle_request(state, req, peer.ip(), &request_id)
+            .await
+            .into_response()
     }
+    .instrument(span)
+    .await;
+
+    let request_id = HeaderValue::from_str(&request_id).unwrap();
+    let headers = response.headers_mut();
+    headers.insert("x-amzn-RequestId", request_id.clone());
+    headers.insert("apigw-requestid", request_id);
+    response
 }
 
-async fn handle_all(req: Request) -> impl IntoResponse {
-    let method = req.method().to_string();
+async fn handle_request(
+    state: AppState,
+    req: Request,
+    peer: IpAddr,
+    request_id: &str,
+) -> Result<axum::response::Response, ProxyError> {
+    let method = req.method().clone();
+    let version = req.version();
     let path = req.uri().path().to_string();
 
-    let headers: HashMap<_, _> = match req
-        .headers()
-        .iter()
-        .map(|(name, value)| {
-            String::from_utf8(value.as_bytes().to_vec()).map(|x| (name.to_string(), x))
-        })
-        .collect()
-    {
-        Ok(r) => r,
-        Err(err) => {
-            eprintln!("Error reading headers: {}", err);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error reading request headers.".to_string(),
-            )
-                .into_response();
-        }
+    // API Gateway と同様にマッチするルートがなければ 404
+    let Some(route) = state.routes.find(&method, &path) else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "message": "Not Found" })),
+        )
+            .into_response());
     };
-
+    let function = route.function.clone();
+    let route_key = route.key.cloned();
+    let path_parameters = route.path_parameters;
     let query_string = req.uri().query().unwrap_or("").to_string();
-    let query: HashMap<_, _> =
-        url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
-            .into_owned()
-            .collect();
-
-    let body_bytes = match axum::body::to_bytes(req.into_body(), usize::MAX).await {
-        Ok(bytes) => bytes.to_vec(),
-        Err(e) => {
-            eprintln!("Error reading body: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error reading request body.".to_string(),
-            )
-                .into_response();
-        }
+    let mut headers = req.headers().clone();
+    let source_ip = source_ip(peer, &headers, &state.trusted_proxies);
+    // HTTP/2 では Host ヘッダーの代わりに :authority が使われるので、Function URL と同じく host に入れる
+    if !headers.contains_key(header::HOST)
+        && let Some(authority) = req.uri().authority()
+        && let Ok(host) = HeaderValue::from_str(authority.as_str())
+    {
+        headers.insert(header::HOST, host);
+    }
+    add_forwarded_headers(
+        &mut headers,
+        peer,
+        state.port,
+        state.scheme,
+        state.trusted_proxies.iter().any(|net| net.contains(&peer)),
+    );
+
+    let body_bytes = match stream::read_body(req.into_body(), MAX_PAYLOAD_SIZE).await {
+        Ok(Some(bytes)) => bytes.to_vec(),
+        Ok(None) => return Err(ProxyError::RequestTooLarge(MAX_PAYLOAD_SIZE)),
+        Err(e) => return Err(ProxyError::RequestBody(e.to_string())),
     };
 
-    let now: DateTime<Utc> = Utc::now();
-    let formatted_time = now.format("%d/%b/%Y:%H:%M:%S %z").to_string();
-
-    // TODO: cookie not supported
-    let body = serde_json::json!({
-      "version": "2.0",
-      "routeKey": "$default",
-      "rawPath": path.clone(),
-      "rawQueryString": query_string,
-      "cookies": [
-        "cookie1=value1",
-        "cookie2=value2"
-      ],
-      "headers": headers,
-      "queryStringParameters": query,
-      "requestContext": {
-        "accountId": "anonymous",
-        "apiId": "xxxxxxxxxx",
-        "domainName": "xxxxxxxxxx.lambda-url.ap-northeast-1.on.aws",
-        "domainPrefix": "xxxxxxxxxx",
-        "http": {
-          "method": method.clone(),
-          "path": path,
-          "protocol": "HTTP/1.1",
-          "sourceIp": "1.2.3.4",
-          "userAgent": "curl/7.81.0"
+    let body = event::build_event(
+        function.format,
+        &RequestParts {
+            method,
+            version,
+            path,
+            query_string,
+            headers,
+            body: body_bytes,
+            time: Utc::now(),
+            request_id: request_id.to_string(),
+            source_ip,
+            api: function.api.clone(),
+            route_key,
+            path_parameters,
         },
-        "requestId": "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx",
-        "routeKey": "$default",
-        "stage": "$default",
-        "time": formatted_time,
-        "timeEpoch": now.timestamp_millis(),
-      },
-      "body": general_purpose::STANDARD.encode(&body_bytes),
-      "isBase64Encoded": true
-    });
+    );
 
-    // TODO: 最大サイズ確認
-    let response = reqwest::Client::new()
-        .request(
-            reqwest::Method::POST,
-            env::var("BACKEND").expect("BACKEND is not set"),
-        )
-        .body(serde_json::to_vec(&body).unwrap())
-        .send()
-        .await
-        .unwrap();
+    // base64 エンコードなどで元のボディより大きくなるので、イベント全体のサイズで判定する
+    let event = serde_json::to_vec(&body).unwrap();
+    if event.len() > MAX_PAYLOAD_SIZE {
+        return Err(ProxyError::RequestTooLarge(MAX_PAYLOAD_SIZE));
+    }
+    let response = function
+        .pool
+        .invoke(event, request_id, function.timeout)
+        .await?;
 
-    if response.status().is_server_error() {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Error calling backend.".to_string(),
-        )
-            .into_response();
+    let function_error = response.headers.contains_key("x-amz-function-error");
+    if function.invoke_mode == InvokeMode::ResponseStream && !function_error {
+        return stream::stream_response(response, state.streaming_response_limit).await;
     }
 
-    let lambda_response: LambdaResponse =
-        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    let Some(body) = stream::read_body(response.body, MAX_PAYLOAD_SIZE)
+        .await
+        .map_err(ProxyError::from_body_error)?
+    else {
+        return Err(ProxyError::Function {
+            payload: serde_json::json!({
+                "errorMessage": format!(
+                    "Response payload size exceeded maximum allowed payload size ({} bytes).",
+                    MAX_PAYLOAD_SIZE
+                ),
+                "errorType": "Function.ResponseSizeTooLarge",
+            }),
+            debug: state.debug_errors,
+        });
+    };
+    if let Some(payload) = function_error_payload(function_error, &body) {
+        return Err(ProxyError::Function {
+            payload,
+            debug: state.debug_errors,
+        });
+    }
+    LambdaResponse::from_payload(function.format, &body)?.into_response()
+}
 
-    let mut r: Response<axum::body::Body> = Response::builder()
-        .status(lambda_response.status_code)
-        .body(lambda_response.body().into())
-        .unwrap();
-    lambda_response.headers.into_iter().for_each(|(k, v)| {
-        r.headers_mut().insert(
-            HeaderName::try_from(k.as_str()).unwrap(),
-            v.parse().unwrap(),
+/// 信頼するプロキシからの接続であれば、X-Forwarded-For を右から辿って最初の信頼しないアドレスを返す
+fn source_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !trusted(&peer) {
+        return peer;
+    }
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !trusted(ip))
+        .or(forwarded.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+/// API Gateway / Function URL が付けるヘッダーを追加する。X-Forwarded-For には接続元を追記し、
+/// X-Forwarded-Proto / Port は信頼するプロキシからのものでなければ上書きする
+fn add_forwarded_headers(
+    headers: &mut HeaderMap,
+    peer: IpAddr,
+    port: u16,
+    scheme: &'static str,
+    trusted: bool,
+) {
+    let forwarded_for = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .chain([peer.to_string()])
+        .collect::<Vec<_>>()
+        .join(", ");
+    headers.insert(
+        "x-forwarded-for",
+        HeaderValue::from_str(&forwarded_for).unwrap(),
+    );
+    if !trusted || !headers.contains_key("x-forwarded-proto") {
+        headers.insert("x-forwarded-proto", HeaderValue::from_static(scheme));
+    }
+    if !trusted || !headers.contains_key("x-forwarded-port") {
+        headers.insert("x-forwarded-port", HeaderValue::from(port));
+    }
+    if !headers.contains_key("x-amzn-trace-id") {
+        headers.insert(
+            "x-amzn-trace-id",
+            HeaderValue::from_str(&event::new_trace_id()).unwrap(),
         );
-    });
+    }
+}
 
-    r
+/// X-Amz-Function-Error ヘッダーがあるか、statusCode がなく errorMessage / errorType を持つ
+/// ペイロードなら関数のエラーとして扱う
+fn function_error_payload(has_header: bool, body: &[u8]) -> Option<serde_json::Value> {
+    let payload = serde_json::from_slice::<serde_json::Value>(body).ok();
+    if has_header {
+        return Some(payload.unwrap_or_else(
+            || serde_json::json!({ "errorMessage": String::from_utf8_lossy(body) }),
+        ));
+    }
+    payload.filter(|payload| {
+        payload.get("statusCode").is_none()
+            && (payload.get("errorMessage").is_some() || payload.get("errorType").is_some())
+    })
 }
 
 // --- メイン関数 ---
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "axum_all_paths=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let cli = Cli::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("error: {}\n\nFor more information, try '--help'.", e);
+        std::process::exit(2);
+    });
+    match cli.command {
+        // head などにパイプした場合に Broken pipe で panic しないよう書き込みのエラーは無視する
+        Command::Help => {
+            let _ = writeln!(std::io::stdout(), "{}", cli::USAGE);
+            return;
+        }
+        Command::Version => {
+            let _ = writeln!(
+                std::io::stdout(),
+                "aws-lambda-proxy {}",
+                env!("CARGO_PKG_VERSION")
+            );
+            return;
+        }
+        _ => {}
+    }
+    logging::init(cli.log_format);
 
-    // ルーティングを設定
-    let app = Router::new()
-        // ルーティングにマッチしなかったすべてを handle_all にフォールバックさせる
-        .fallback(handle_all)
-        // Tower ServiceBuilderを使用してミドルウェアを追加 (例: ロギング)
-        .layer(ServiceBuilder::new().layer(tower_http::trace::TraceLayer::new_for_http()));
+    let config = cli.config().unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    match &cli.command {
+        Command::Serve => serve(config).await,
+        Command::Invoke {
+            event_file,
+            function,
+        } => invoke(config, event_file, function.as_deref()).await,
+        Command::ValidateConfig => validate_config(&config),
+        Command::Help | Command::Version => unreachable!(),
+    }
+}
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
-    tracing::debug!("listening on {}", addr);
+async fn serve(config: Config) {
+    // 関数のプロセスを終了させるためのシグナル
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut supervisors = Vec::new();
+    let plan = Plan::new(&config).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    let listeners = listeners(&config).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    let (routes, default_function) = start_functions(plan, &config, &shutdown_rx, &mut supervisors)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        });
+    for route in &routes {
+        tracing::debug!("route {} -> {}", route.key, route.function.name);
+    }
+    let routes = Arc::new(RouteTable::new(routes, default_function));
 
-    // サーバーを起動
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    let trusted_proxies = Arc::new(config.trusted_proxies.iter().map(|net| net.0).collect());
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal()) // ここでシャットダウンシグナルを渡す
-        .await
-        .unwrap();
+    // シグナルを受けたら全てのリスナーをグレースフルシャットダウンする
+    let (stop_tx, stop_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = stop_tx.send(true);
+    });
+
+    let mut servers = Vec::new();
+    for (addr, tls) in listeners {
+        // ルーティングを設定
+        let app = Router::new()
+            // ルーティングにマッチしなかったすべてを handle_all にフォールバックさせる
+            .fallback(handle_all)
+            // Tower ServiceBuilderを使用してミドルウェアを追加 (例: ロギング)
+            .layer(ServiceBuilder::new().layer(tower_http::trace::TraceLayer::new_for_http()))
+            .with_state(AppState {
+                routes: routes.clone(),
+                debug_errors: config.debug_errors,
+                streaming_response_limit: config
+                    .streaming_response_limit
+                    .unwrap_or(DEFAULT_STREAMING_RESPONSE_LIMIT),
+                trusted_proxies: Arc::clone(&trusted_proxies),
+                port: addr.port(),
+                scheme: if tls.is_some() { "https" } else { "http" },
+            });
+
+        // サーバーを起動。HTTP/1.1 と HTTP/2 の両方を受け付ける (TLS なしは h2c の prior knowledge)
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("error: failed to listen on {}: {}", addr, e);
+                std::process::exit(1);
+            });
+        tracing::debug!("listening on {}", addr);
+        let mut stop_rx = stop_rx.clone();
+        servers.push(match tls {
+            Some(acceptor) => tokio::spawn(async move {
+                tls::serve(listener, acceptor, app, stop_rx).await;
+                Ok(())
+            }),
+            None => tokio::spawn(
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(async move {
+                    let _ = stop_rx.wait_for(|stop| *stop).await;
+                })
+                .into_future(),
+            ),
+        });
+    }
+    for server in servers {
+        server.await.unwrap().unwrap();
+    }
 
     // グレースフルシャットダウンが完了すると、この下のコードが実行される
+    let _ = shutdown_tx.send(true);
+    for supervisor in supervisors {
+        let _ = supervisor.await;
+    }
     println!("Server has shut down.");
 }
 
+/// 待ち受けるアドレスと、TLS の場合は証明書を読み込んだ TlsAcceptor
+fn listeners(config: &Config) -> Result<Vec<(SocketAddr, Option<TlsAcceptor>)>, String> {
+    if config.listeners.is_empty() {
+        return Ok(vec![(SocketAddr::from(([0, 0, 0, 0], 8000)), None)]);
+    }
+    config
+        .listeners
+        .iter()
+        .enumerate()
+        .map(|(i, listener)| {
+            let tls = listener
+                .tls
+                .as_ref()
+                .map(tls::acceptor)
+                .transpose()
+                .map_err(|e| format!("listeners[{}].tls: {}", i, e))?;
+            Ok((listener.address.0, tls))
+        })
+        .collect()
+}
+
+/// 起動する関数の実行環境
+enum EnvironmentPlan {
+    /// URL ごとに 1 つの実行環境
+    Http(Vec<String>),
+    /// 組み込みの Runtime API。process が指定された場合は関数のプロセスも起動する
+    Runtime {
+        base_addr: SocketAddr,
+        concurrency: u16,
+        /// 関数の設定の context の account_id とリージョンから組み立てる
+        function_arn: String,
+        process: Option<FunctionProcess>,
+    },
+}
+
+struct FunctionPlan {
+    name: String,
+    environments: EnvironmentPlan,
+    reserved_concurrency: Option<usize>,
+}
+
+struct RoutePlan {
+    /// None は $default ルート
+    key: Option<RouteKey>,
+    /// Plan::functions のインデックス
+    function: usize,
+    format: EventFormat,
+    invoke_mode: InvokeMode,
+    timeout: Duration,
+    api: Arc<ApiContext>,
+}
+
+/// 設定と SAM テンプレートから解決した関数とルート。
+/// validate-config と serve / invoke で同じ検証を通すため、何も起動せずに全ての値を確定させる
+struct Plan {
+    functions: Vec<FunctionPlan>,
+    /// $default ルートは先に追加したものを使う
+    routes: Vec<RoutePlan>,
+}
+
+impl Plan {
+    fn new(config: &Config) -> Result<Plan, String> {
+        let defaults = &config.defaults;
+        // コマンドライン引数の値は他の全ての設定より優先する
+        let override_timeout = config
+            .overrides
+            .timeout
+            .map(timeout_from_secs)
+            .transpose()
+            .map_err(|e| format!("--timeout: {}", e))?;
+        let override_format = config.overrides.format;
+        let mut functions = Vec::new();
+        let mut routes = Vec::new();
+
+        // 関数ごとに実行環境のプールを作る。ルートごとに形式が異なっても同じ関数ならプールを共有する
+        let mut function_configs: HashMap<&str, (usize, FunctionConfig)> = HashMap::new();
+        for (name, function) in &config.functions {
+            let function = function.or(defaults);
+            let environments = if function.backends.is_empty() {
+                let api = function.context.api_context();
+                let process = match &function.command {
+                    Some(command) => {
+                        let mut process = FunctionProcess::new(
+                            &command.0,
+                            name,
+                            function.memory_size.unwrap_or(128),
+                            &api.region,
+                        )
+                        .map_err(|e| format!("functions.{}.command: {}", name, e))?;
+                        process.env = function.environment.clone();
+                        Some(process)
+                    }
+                    None => None,
+                };
+                let base_addr = function
+                    .runtime_api
+                    .map_or(SocketAddr::from(([127, 0, 0, 1], 0)), |addr| addr.0);
+                let concurrency = function.concurrency.unwrap_or(1);
+                // 実行環境ごとに base_addr のポートから順に使う
+                if base_addr.port() != 0 && base_addr.port().checked_add(concurrency - 1).is_none()
+                {
+                    return Err(format!(
+                        "functions.{}.runtime_api: port {} with concurrency {} exceeds 65535",
+                        name,
+                        base_addr.port(),
+                        concurrency
+                    ));
+                }
+                EnvironmentPlan::Runtime {
+                    base_addr,
+                    concurrency,
+                    function_arn: api.function_arn(name),
+                    process,
+                }
+            } else {
+                EnvironmentPlan::Http(function.backends.clone())
+            };
+            functions.push(FunctionPlan {
+                name: name.clone(),
+                environments,
+                reserved_concurrency: function.reserved_concurrency,
+            });
+            function_configs.insert(name, (functions.len() - 1, function));
+        }
+
+        for (i, route) in config.routes.iter().enumerate() {
+            let (index, function) = &function_configs[route.function.as_str()];
+            let key = match route.route.as_str() {
+                "$default" => None,
+                key => Some(
+                    key.parse()
+                        .map_err(|e| format!("routes[{}].route: {}", i, e))?,
+                ),
+            };
+            let timeout = function
+                .timeout
+                .map(timeout_from_secs)
+                .transpose()
+                .map_err(|e| format!("functions.{}.timeout: {}", route.function, e))?
+                .unwrap_or(DEFAULT_TIMEOUT);
+            routes.push(RoutePlan {
+                key,
+                function: *index,
+                format: override_format.unwrap_or_else(|| {
+                    route
+                        .format
+                        .or(function.format)
+                        .map_or(EventFormat::V2, |f| f.0)
+                }),
+                invoke_mode: route
+                    .invoke_mode
+                    .or(function.invoke_mode)
+                    .map_or(InvokeMode::Buffered, |m| m.0),
+                timeout: override_timeout.unwrap_or(timeout),
+                api: Arc::new(route.context.or(&function.context).api_context()),
+            });
+        }
+
+        // SAM テンプレートが指定された場合はテンプレートの関数とルートを追加する
+        if let Some(template) = &config.sam_template {
+            for sam_function in sam::load(template)? {
+                let name = sam_function.logical_id.clone();
+                // 設定ファイルに同じ名前の関数があればそれを、なければ BACKEND_<論理 ID> の URL か
+                // FUNCTION_COMMAND_<論理 ID> か CodeUri の bootstrap を起動する
+                let (index, function) = if let Some((index, function)) =
+                    function_configs.get(name.as_str())
+                {
+                    (*index, function.clone())
+                } else {
+                    let environments = if let Ok(backend) = env::var(format!("BACKEND_{}", name)) {
+                        EnvironmentPlan::Http(backend.split(',').map(String::from).collect())
+                    } else {
+                        let api = defaults.context.api_context();
+                        let memory_size = sam_function.memory_size.unwrap_or(128);
+                        let mut process = match (
+                            env::var(format!("FUNCTION_COMMAND_{}", name)),
+                            &sam_function.code_dir,
+                        ) {
+                            (Ok(command), _) => FunctionProcess::from_command_line(
+                                &command,
+                                &name,
+                                memory_size,
+                                &api.region,
+                            )
+                            .map_err(|e| format!("FUNCTION_COMMAND_{}: {}", name, e))?,
+                            (Err(_), Some(code_dir))
+                                if sam_function
+                                    .runtime
+                                    .as_deref()
+                                    .is_none_or(|runtime| runtime.starts_with("provided")) =>
+                            {
+                                FunctionProcess::new(
+                                    &[code_dir.join("bootstrap").to_string_lossy().into_owned()],
+                                    &name,
+                                    memory_size,
+                                    &api.region,
+                                )?
+                            }
+                            _ => {
+                                tracing::warn!(
+                                    "{}: set BACKEND_{} or FUNCTION_COMMAND_{} to run this function",
+                                    name,
+                                    name,
+                                    name
+                                );
+                                continue;
+                            }
+                        };
+                        process.env = sam_function.environment.clone();
+                        EnvironmentPlan::Runtime {
+                            base_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+                            concurrency: defaults.concurrency.unwrap_or(1),
+                            function_arn: api.function_arn(&name),
+                            process: Some(process),
+                        }
+                    };
+                    functions.push(FunctionPlan {
+                        name: name.clone(),
+                        environments,
+                        reserved_concurrency: defaults.reserved_concurrency,
+                    });
+                    (functions.len() - 1, defaults.clone())
+                };
+
+                // 同じ関数でもイベントごとにペイロードの形式が異なるので、プールを共有して形式ごとにルートを作る
+                let timeout = match (override_timeout, sam_function.timeout) {
+                    (Some(timeout), _) => timeout,
+                    (None, Some(timeout)) => timeout_from_secs(timeout)
+                        .map_err(|e| format!("{}: {}.Timeout: {}", template.display(), name, e))?,
+                    (None, None) => function
+                        .timeout
+                        .map_or(Ok(DEFAULT_TIMEOUT), timeout_from_secs)?,
+                };
+                let api = Arc::new(function.context.api_context());
+                let route_for =
+                    |key: Option<RouteKey>, format: EventFormat, invoke_mode: InvokeMode| {
+                        RoutePlan {
+                            key,
+                            function: index,
+                            format: override_format.unwrap_or(format),
+                            invoke_mode,
+                            timeout,
+                            api: api.clone(),
+                        }
+                    };
+                for event in &sam_function.events {
+                    routes.push(route_for(
+                        event.route_key.clone(),
+                        event.format,
+                        InvokeMode::Buffered,
+                    ));
+                }
+                if let Some(invoke_mode) = sam_function.function_url {
+                    routes.push(route_for(None, EventFormat::V2, invoke_mode));
+                }
+            }
+        }
+
+        if routes.is_empty() {
+            return Err("no function is configured".to_string());
+        }
+        Ok(Plan { functions, routes })
+    }
+}
+
+/// Plan の関数を起動し、ルートと $default ルートの関数を作る
+async fn start_functions(
+    plan: Plan,
+    config: &Config,
+    shutdown_rx: &watch::Receiver<bool>,
+    supervisors: &mut Vec<JoinHandle<()>>,
+) -> Result<(Vec<Route>, Option<Arc<Function>>), String> {
+    // バックエンドへの HTTP クライアントは全ての関数で共有し、keep-alive の接続を再利用する
+    let mut client = reqwest::Client::builder().tcp_nodelay(true);
+    if let Some(pool_size) = config.backend_pool_size {
+        client = client.pool_max_idle_per_host(pool_size);
+    }
+    if config.backend_http2 {
+        client = client.http2_prior_knowledge();
+    }
+    let client = client.build().expect("failed to build HTTP client");
+
+    let mut pools = Vec::new();
+    for function in &plan.functions {
+        let environments = match &function.environments {
+            EnvironmentPlan::Http(urls) => {
+                http_environments(urls.iter().map(String::as_str), &client)
+            }
+            EnvironmentPlan::Runtime {
+                base_addr,
+                concurrency,
+                function_arn,
+                process,
+            } => start_runtime_environments(
+                function_arn,
+                *base_addr,
+                *concurrency,
+                process.clone(),
+                shutdown_rx,
+                supervisors,
+            )
+            .await
+            .map_err(|e| format!("{}: {}", function.name, e))?,
+        };
+        let pool = Pool::new(environments, function.reserved_concurrency)
+            .map_err(|e| format!("{}: {}", function.name, e))?;
+        pools.push(Arc::new(pool));
+    }
+
+    let mut default_function = None;
+    let mut routes = Vec::new();
+    for route in plan.routes {
+        let function = Arc::new(Function {
+            name: plan.functions[route.function].name.clone(),
+            pool: pools[route.function].clone(),
+            format: route.format,
+            invoke_mode: route.invoke_mode,
+            timeout: route.timeout,
+            api: route.api,
+        });
+        match route.key {
+            Some(key) => routes.push(Route { key, function }),
+            None => {
+                default_function.get_or_insert(function);
+            }
+        }
+    }
+    Ok((routes, default_function))
+}
+
+/// イベントファイルで関数を 1 回呼び出し、レスポンスのペイロードを標準出力に書く。
+/// 関数がエラーを返した場合も出力し、終了コードを 1 にする
+async fn invoke(config: Config, event_file: &Path, function_name: Option<&str>) {
+    let fail = |message: String| -> ! {
+        eprintln!("error: {}", message);
+        std::process::exit(1);
+    };
+    let event = if event_file == Path::new("-") {
+        let mut event = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut event)
+            .map(|_| event)
+            .map_err(|e| e.to_string())
+    } else {
+        std::fs::read(event_file).map_err(|e| e.to_string())
+    }
+    .unwrap_or_else(|e| fail(format!("failed to read {}: {}", event_file.display(), e)));
+    if let Err(e) = serde_json::from_slice::<serde_json::Value>(&event) {
+        fail(format!("{}: invalid JSON: {}", event_file.display(), e));
+    }
+    if event.len() > MAX_PAYLOAD_SIZE {
+        fail(ProxyError::RequestTooLarge(MAX_PAYLOAD_SIZE).to_string());
+    }
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut supervisors = Vec::new();
+    let plan = Plan::new(&config).unwrap_or_else(|e| fail(e));
+    let (routes, default_function) = start_functions(plan, &config, &shutdown_rx, &mut supervisors)
+        .await
+        .unwrap_or_else(|e| fail(e));
+    let mut functions = default_function
+        .iter()
+        .chain(routes.iter().map(|route| &route.function));
+    let function = match function_name {
+        Some(name) => functions.find(|function| function.name == name),
+        None => functions.next(),
+    }
+    .unwrap_or_else(|| {
+        fail(match function_name {
+            Some(name) => format!("unknown function: {}", name),
+            None => "no function is configured".to_string(),
+        })
+    })
+    .clone();
+
+    let request_id = event::new_request_id();
+    let result = async {
+        let response = function
+            .pool
+            .invoke(event, &request_id, function.timeout)
+            .await?;
+        let function_error = response.headers.contains_key("x-amz-function-error");
+        let body = stream::read_body(response.body, MAX_PAYLOAD_SIZE)
+            .await
+            .map_err(ProxyError::from_body_error)?
+            .ok_or_else(|| {
+                ProxyError::InvalidResponse(format!(
+                    "Response payload size exceeded maximum allowed payload size ({} bytes).",
+                    MAX_PAYLOAD_SIZE
+                ))
+            })?;
+        let function_error = function_error_payload(function_error, &body).is_some();
+        Ok::<_, ProxyError>((body, function_error))
+    }
+    .instrument(tracing::info_span!("invoke", request_id = %request_id))
+    .await;
+
+    let _ = shutdown_tx.send(true);
+    for supervisor in supervisors {
+        let _ = supervisor.await;
+    }
+    match result {
+        Ok((body, function_error)) => {
+            let mut stdout = std::io::stdout();
+            let _ = stdout
+                .write_all(&body)
+                .and_then(|_| stdout.write_all(b"\n"));
+            if function_error {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => fail(e.to_string()),
+    }
+}
+
+/// serve / invoke と同じく関数とルートを解決できることを確認し、ルートを出力する
+fn validate_config(config: &Config) {
+    let plan = Plan::new(config).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = listeners(config) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+    for route in &plan.routes {
+        let key = route
+            .key
+            .as_ref()
+            .map_or("$default".to_string(), |key| key.to_string());
+        println!("{} -> {}", key, plan.functions[route.function].name);
+    }
+    println!("configuration is valid");
+}
+
+/// URL をそれぞれ 1 つの実行環境とする
+fn http_environments<'a>(
+    urls: impl IntoIterator<Item = &'a str>,
+    client: &reqwest::Client,
+) -> Vec<Backend> {
+    urls.into_iter()
+        .map(|url| Backend::Http {
+            url: url.trim().to_string(),
+            client: client.clone(),
+        })
+        .collect()
+}
+
+/// concurrency の数だけ Runtime API を起動し、そこに接続してきた関数にイベントを渡す
+///
+/// process が指定された場合は実行環境ごとに関数のプロセスも起動する。
+async fn start_runtime_environments(
+    function_arn: &str,
+    base_addr: SocketAddr,
+    concurrency: u16,
+    process: Option<FunctionProcess>,
+    shutdown: &watch::Receiver<bool>,
+    supervisors: &mut Vec<JoinHandle<()>>,
+) -> Result<Vec<Backend>, String> {
+    let mut environments = Vec::new();
+    for i in 0..concurrency {
+        let runtime_api = Arc::new(RuntimeApi::new(function_arn.to_string()));
+        // ポートが 0 の場合はそれぞれランダムなポートになる。範囲は Plan::new で検証している
+        let mut addr = base_addr;
+        if addr.port() != 0 {
+            addr.set_port(addr.port() + i);
+        }
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("failed to listen on {} for the runtime api: {}", addr, e))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| format!("failed to listen on {} for the runtime api: {}", addr, e))?;
+        tracing::debug!("runtime api listening on {}", addr);
+        tokio::spawn(axum::serve(listener, runtime_api.clone().router()).into_future());
+
+        if let Some(process) = &process {
+            supervisors.push(process::supervise(
+                process.clone(),
+                runtime_api.clone(),
+                addr,
+                shutdown.clone(),
+            ));
+        }
+        environments.push(Backend::Runtime(runtime_api));
+    }
+    Ok(environments)
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -0,0 +1,129 @@
+use axum::body::Body;
+use futures_util::StreamExt;
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::backend::{Backend, BackendResponse};
+use crate::error::{ProxyError, TimedOut};
+
+/// 関数の実行環境のプール。1 つの実行環境は同時に 1 つの呼び出しだけを処理する
+pub struct Pool {
+    environments: Vec<Backend>,
+    idle: Mutex<Vec<usize>>,
+    available: Arc<Semaphore>,
+    reserved_concurrency: Option<usize>,
+    /// 実行中と実行環境の空き待ちの呼び出しの数
+    concurrency: AtomicUsize,
+}
+
+impl Pool {
+    /// 実行環境が 1 つもなければ呼び出しが永久に待つので作らない
+    pub fn new(
+        environments: Vec<Backend>,
+        reserved_concurrency: Option<usize>,
+    ) -> Result<Self, String> {
+        if environments.is_empty() {
+            return Err("no execution environment".to_string());
+        }
+        Ok(Pool {
+            idle: Mutex::new((0..environments.len()).rev().collect()),
+            available: Arc::new(Semaphore::new(environments.len())),
+            environments,
+            reserved_concurrency,
+            concurrency: AtomicUsize::new(0),
+        })
+    }
+
+    /// 空いている実行環境でイベントを処理する。全て使用中の場合は空くまで待つ。
+    /// 関数がイベントを受け取ってから timeout までにレスポンスを返し終えなければ実行環境を作り直す
+    pub async fn invoke(
+        self: &Arc<Self>,
+        event: Vec<u8>,
+        request_id: &str,
+        timeout: Duration,
+    ) -> Result<BackendResponse, ProxyError> {
+        let concurrency = ConcurrencyGuard::new(self.clone());
+        if self
+            .reserved_concurrency
+            .is_some_and(|reserved| concurrency.count > reserved)
+        {
+            return Err(ProxyError::TooManyRequests);
+        }
+
+        let permit = self.available.clone().acquire_owned().await.unwrap();
+        let index = self.idle.lock().unwrap().pop().unwrap();
+        let lease = Lease {
+            pool: self.clone(),
+            index,
+            _permit: permit,
+            _concurrency: concurrency,
+        };
+        tracing::debug!("invoking environment {}", index);
+
+        let environment = &self.environments[index];
+        let (mut response, deadline) = match environment.invoke(event, request_id, timeout).await {
+            Ok(invoked) => invoked,
+            Err(ProxyError::Timeout(timeout)) => {
+                tracing::error!("{}", TimedOut(timeout));
+                environment.recycle();
+                return Err(ProxyError::Timeout(timeout));
+            }
+            Err(e) => return Err(e),
+        };
+        // レスポンスのボディを読み終えるまで実行環境は使用中のままにする
+        let body = futures_util::stream::unfold(
+            Some((response.body.into_data_stream(), lease)),
+            move |state| async move {
+                let (mut stream, lease) = state?;
+                match tokio::time::timeout_at(deadline, stream.next()).await {
+                    Ok(chunk) => Some((chunk?, Some((stream, lease)))),
+                    Err(_) => {
+                        tracing::error!("{}", TimedOut(timeout));
+                        lease.pool.environments[lease.index].recycle();
+                        Some((Err(axum::Error::new(TimedOut(timeout))), None))
+                    }
+                }
+            },
+        );
+        response.body = Body::from_stream(body);
+        Ok(response)
+    }
+}
+
+struct ConcurrencyGuard {
+    pool: Arc<Pool>,
+    count: usize,
+}
+
+impl ConcurrencyGuard {
+    fn new(pool: Arc<Pool>) -> Self {
+        let count = pool.concurrency.fetch_add(1, Ordering::SeqCst) + 1;
+        ConcurrencyGuard { pool, count }
+    }
+}
+
+impl Drop for ConcurrencyGuard {
+    fn drop(&mut self) {
+        self.pool.concurrency.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 使用中の実行環境。破棄されると空きに戻る
+struct Lease {
+    pool: Arc<Pool>,
+    index: usize,
+    _permit: OwnedSemaphorePermit,
+    _concurrency: ConcurrencyGuard,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.pool.idle.lock().unwrap().push(self.index);
+    }
+}
@@ -0,0 +1,183 @@
+use rand::Rng;
+use std::{collections::HashMap, net::SocketAddr, process::ExitStatus, sync::Arc, time::Duration};
+use tokio::{process::Command, sync::watch, task::JoinHandle};
+
+use crate::runtime_api::RuntimeApi;
+
+/// 異常終了した関数を再起動するまでの待ち時間
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// SIGTERM を送ってから SIGKILL するまでの猶予
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// プロキシから起動する関数の実行ファイル
+#[derive(Clone)]
+pub struct FunctionProcess {
+    pub command: String,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub function_name: String,
+    pub memory_size: u32,
+    /// AWS_REGION に設定するリージョン
+    pub region: String,
+}
+
+impl FunctionProcess {
+    /// [実行ファイルのパス, 引数...] から作る
+    pub fn new(
+        command: &[String],
+        function_name: &str,
+        memory_size: u32,
+        region: &str,
+    ) -> Result<Self, String> {
+        let (command, args) = command.split_first().ok_or("command is empty")?;
+        Ok(FunctionProcess {
+            command: command.clone(),
+            args: args.to_vec(),
+            env: HashMap::new(),
+            function_name: function_name.to_string(),
+            memory_size,
+            region: region.to_string(),
+        })
+    }
+
+    /// 空白区切りのコマンドライン ("./bootstrap --flag") から作る
+    pub fn from_command_line(
+        command_line: &str,
+        function_name: &str,
+        memory_size: u32,
+        region: &str,
+    ) -> Result<Self, String> {
+        let args: Vec<String> = command_line.split_whitespace().map(String::from).collect();
+        Self::new(&args, function_name, memory_size, region)
+    }
+
+    /// Lambda の実行環境が設定する環境変数
+    fn lambda_env(&self, runtime_api_addr: SocketAddr) -> HashMap<String, String> {
+        let log_stream_id: [u8; 16] = rand::rng().random();
+        let log_stream_id: String = log_stream_id.iter().map(|b| format!("{:02x}", b)).collect();
+        HashMap::from([
+            (
+                "AWS_LAMBDA_RUNTIME_API".to_string(),
+                runtime_api_addr.to_string(),
+            ),
+            (
+                "AWS_LAMBDA_FUNCTION_NAME".to_string(),
+                self.function_name.clone(),
+            ),
+            (
+                "AWS_LAMBDA_FUNCTION_MEMORY_SIZE".to_string(),
+                self.memory_size.to_string(),
+            ),
+            (
+                "AWS_LAMBDA_FUNCTION_VERSION".to_string(),
+                "$LATEST".to_string(),
+            ),
+            (
+                "AWS_LAMBDA_INITIALIZATION_TYPE".to_string(),
+                "on-demand".to_string(),
+            ),
+            (
+                "AWS_LAMBDA_LOG_GROUP_NAME".to_string(),
+                format!("/aws/lambda/{}", self.function_name),
+            ),
+            (
+                "AWS_LAMBDA_LOG_STREAM_NAME".to_string(),
+                format!(
+                    "{}/[$LATEST]{}",
+                    chrono::Utc::now().format("%Y/%m/%d"),
+                    log_stream_id
+                ),
+            ),
+            ("AWS_REGION".to_string(), self.region.clone()),
+            ("AWS_DEFAULT_REGION".to_string(), self.region.clone()),
+        ])
+    }
+}
+
+/// 関数のプロセスを起動し、終了したら再起動する。shutdown が true になったらプロセスを終了させる
+pub fn supervise(
+    process: FunctionProcess,
+    runtime_api: Arc<RuntimeApi>,
+    runtime_api_addr: SocketAddr,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let spawned = Command::new(&process.command)
+                .args(&process.args)
+                .envs(&process.env)
+                .envs(process.lambda_env(runtime_api_addr))
+                // 関数のログはプロキシのログと同じく標準エラー出力に書く
+                .stdout(std::io::stderr())
+                .kill_on_drop(true)
+                .spawn();
+            match spawned {
+                Ok(mut child) => {
+                    tracing::info!(
+                        "started function {} (pid {:?})",
+                        process.function_name,
+                        child.id()
+                    );
+                    tokio::select! {
+                        status = child.wait() => {
+                            let status = status.map(describe_exit_status).unwrap_or_else(|e| e.to_string());
+                            tracing::warn!("function {} exited: {}", process.function_name, status);
+                            runtime_api.runtime_exited(&status);
+                        }
+                        _ = runtime_api.recycle_requested() => {
+                            tracing::info!("restarting function {} after timeout", process.function_name);
+                            let _ = child.kill().await;
+                            runtime_api.runtime_exited("timed out");
+                            continue;
+                        }
+                        _ = shutdown.changed() => {
+                            terminate(&mut child).await;
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("failed to start {}: {}", process.command, e);
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(RESTART_DELAY) => {}
+                _ = shutdown.changed() => return,
+            }
+        }
+    })
+}
+
+/// SIGTERM で終了させ、猶予内に終了しなければ SIGKILL する
+async fn terminate(child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+        if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, child.wait())
+            .await
+            .is_ok()
+        {
+            return;
+        }
+    }
+    let _ = child.kill().await;
+}
+
+/// Lambda の Runtime.ExitError と同じ表記にする
+fn describe_exit_status(status: ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return format!("signal: {}", signal);
+        }
+    }
+    match status.code() {
+        Some(code) => format!("exit status {}", code),
+        None => status.to_string(),
+    }
+}
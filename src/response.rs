@@ -0,0 +1,250 @@
+use axum::http::{HeaderMap, HeaderName, Response, header};
+use base64::Engine;
+use base64::engine::general_purpose;
+use hyper::ext::ReasonPhrase;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::error::ProxyError;
+use crate::event::EventFormat;
+
+/// Lambda から返ってくるレスポンス (payload format 1.0 / 2.0 / ALB 共通)
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LambdaResponse {
+    status_code: u16,
+    status_description: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    multi_value_headers: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    body: String,
+    is_base64_encoded: Option<bool>,
+    #[serde(default)]
+    cookies: Vec<String>,
+}
+
+impl LambdaResponse {
+    /// 関数の戻り値を解釈する。payload format 2.0 では statusCode がない場合、戻り値全体を
+    /// ボディとする 200 のレスポンスとみなす (文字列の場合はその文字列をボディにする)
+    pub fn from_payload(format: EventFormat, payload: &[u8]) -> Result<Self, ProxyError> {
+        let invalid = |e: serde_json::Error| {
+            ProxyError::InvalidResponse(format!("{}: {}", e, String::from_utf8_lossy(payload)))
+        };
+        let value: serde_json::Value = serde_json::from_slice(payload).map_err(invalid)?;
+        if format == EventFormat::V2 && value.get("statusCode").is_none() {
+            let body = match value {
+                serde_json::Value::String(s) => s,
+                value => value.to_string(),
+            };
+            return Ok(LambdaResponse {
+                status_code: 200,
+                status_description: None,
+                headers: HashMap::from([(
+                    header::CONTENT_TYPE.to_string(),
+                    "application/json".to_string(),
+                )]),
+                multi_value_headers: HashMap::new(),
+                body,
+                is_base64_encoded: None,
+                cookies: Vec::new(),
+            });
+        }
+        serde_json::from_value(value).map_err(invalid)
+    }
+
+    fn body(&self) -> Result<Vec<u8>, ProxyError> {
+        if self.is_base64_encoded.unwrap_or(false) {
+            general_purpose::STANDARD
+                .decode(&self.body)
+                .map_err(|e| ProxyError::InvalidResponse(format!("invalid base64 body: {}", e)))
+        } else {
+            Ok(self.body.clone().into_bytes())
+        }
+    }
+
+    pub fn into_response(self) -> Result<Response<axum::body::Body>, ProxyError> {
+        let mut r: Response<axum::body::Body> = Response::builder()
+            .status(self.status_code)
+            .body(self.body()?.into())
+            .map_err(|e| ProxyError::InvalidResponse(e.to_string()))?;
+
+        // ALB の statusDescription ("200 OK" 形式) は reason phrase として返す
+        if let Some(reason) = self
+            .status_description
+            .as_deref()
+            .map(|d| d.trim_start_matches(|c: char| c.is_ascii_digit()).trim())
+            .filter(|reason| !reason.is_empty())
+            .and_then(|reason| ReasonPhrase::try_from(reason.as_bytes()).ok())
+        {
+            r.extensions_mut().insert(reason);
+        }
+
+        insert_headers(
+            r.headers_mut(),
+            self.headers,
+            self.multi_value_headers,
+            self.cookies,
+        )?;
+
+        Ok(r)
+    }
+}
+
+/// Lambda のレスポンスに含まれる headers / multiValueHeaders / cookies を HTTP ヘッダーに変換する
+pub fn insert_headers(
+    header_map: &mut HeaderMap,
+    headers: HashMap<String, String>,
+    multi_value_headers: HashMap<String, Vec<String>>,
+    cookies: Vec<String>,
+) -> Result<(), ProxyError> {
+    let invalid_header =
+        |name: &str| ProxyError::InvalidResponse(format!("invalid header: {}", name));
+    // headers と multiValueHeaders の両方に同じキーがある場合は multiValueHeaders を優先する
+    let multi_value_headers: HashMap<_, _> = multi_value_headers
+        .into_iter()
+        .map(|(k, v)| (k.to_ascii_lowercase(), v))
+        .collect();
+    for (k, v) in headers {
+        if multi_value_headers.contains_key(&k.to_ascii_lowercase()) {
+            continue;
+        }
+        header_map.insert(
+            HeaderName::try_from(k.as_str()).map_err(|_| invalid_header(&k))?,
+            v.parse().map_err(|_| invalid_header(&k))?,
+        );
+    }
+    for (k, values) in multi_value_headers {
+        let name = HeaderName::try_from(k.as_str()).map_err(|_| invalid_header(&k))?;
+        for v in values {
+            header_map.append(&name, v.parse().map_err(|_| invalid_header(&k))?);
+        }
+    }
+    for cookie in cookies {
+        header_map.append(
+            header::SET_COOKIE,
+            cookie
+                .parse()
+                .map_err(|_| invalid_header(header::SET_COOKIE.as_str()))?,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(format: EventFormat, payload: &str) -> Response<axum::body::Body> {
+        LambdaResponse::from_payload(format, payload.as_bytes())
+            .and_then(LambdaResponse::into_response)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    async fn body(response: Response<axum::body::Body>) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    fn values(response: &Response<axum::body::Body>, name: &str) -> Vec<String> {
+        response
+            .headers()
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn v2_infers_response_without_status_code() {
+        for (payload, expected) in [
+            ("\"hello\"", "hello"),
+            ("{\"message\":\"hi\"}", "{\"message\":\"hi\"}"),
+            ("[1,2]", "[1,2]"),
+            ("null", "null"),
+        ] {
+            let r = response(EventFormat::V2, payload);
+            assert_eq!(r.status(), 200, "{}", payload);
+            assert_eq!(values(&r, "content-type"), vec!["application/json"]);
+            assert_eq!(body(r).await, expected);
+        }
+    }
+
+    #[test]
+    fn v1_requires_status_code() {
+        let e = LambdaResponse::from_payload(EventFormat::V1, b"{\"body\":\"hi\"}")
+            .err()
+            .unwrap();
+        assert!(matches!(e, ProxyError::InvalidResponse(_)), "{}", e);
+
+        let e = LambdaResponse::from_payload(EventFormat::V2, b"not json")
+            .err()
+            .unwrap();
+        assert!(matches!(e, ProxyError::InvalidResponse(_)), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn status_headers_and_base64_body() {
+        let r = response(
+            EventFormat::V1,
+            r#"{"statusCode":201,"headers":{"X-A":"1"},"body":"aGk=","isBase64Encoded":true}"#,
+        );
+        assert_eq!(r.status(), 201);
+        assert_eq!(values(&r, "x-a"), vec!["1"]);
+        assert_eq!(body(r).await, "hi");
+    }
+
+    #[test]
+    fn multi_value_headers_override_headers() {
+        let r = response(
+            EventFormat::V1,
+            r#"{"statusCode":200,"headers":{"Content-Type":"text/plain","X-B":"b"},
+                "multiValueHeaders":{"content-type":["text/html"],"X-A":["1","2"]}}"#,
+        );
+        assert_eq!(values(&r, "content-type"), vec!["text/html"]);
+        assert_eq!(values(&r, "x-a"), vec!["1", "2"]);
+        assert_eq!(values(&r, "x-b"), vec!["b"]);
+    }
+
+    #[test]
+    fn one_set_cookie_per_cookie() {
+        let r = response(
+            EventFormat::V2,
+            r#"{"statusCode":200,"cookies":["a=1; Path=/","b=2; Secure"]}"#,
+        );
+        assert_eq!(values(&r, "set-cookie"), vec!["a=1; Path=/", "b=2; Secure"]);
+    }
+
+    #[test]
+    fn alb_status_description() {
+        let r = response(
+            EventFormat::Alb {
+                multi_value_headers: false,
+            },
+            r#"{"statusCode":418,"statusDescription":"418 Short And Stout"}"#,
+        );
+        assert_eq!(r.status(), 418);
+        assert_eq!(
+            r.extensions().get::<ReasonPhrase>().map(|r| r.as_bytes()),
+            Some(&b"Short And Stout"[..])
+        );
+    }
+
+    #[test]
+    fn invalid_header_is_rejected() {
+        let e = LambdaResponse::from_payload(
+            EventFormat::V1,
+            br#"{"statusCode":200,"headers":{"bad header":"x"}}"#,
+        )
+        .and_then(LambdaResponse::into_response)
+        .err()
+        .unwrap();
+        assert_eq!(
+            e.to_string(),
+            "invalid function response: invalid header: bad header"
+        );
+    }
+}
@@ -0,0 +1,378 @@
+use axum::http::Method;
+use percent_encoding::percent_decode_str;
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use crate::function::Function;
+
+/// HTTP API のルートキー ("GET /users/{id}", "ANY /admin/{proxy+}")。$default ルートは RouteTable で扱う
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteKey {
+    /// None は ANY
+    method: Option<Method>,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    /// {proxy+} のように末尾の 1 つ以上のセグメントにマッチする
+    Greedy(String),
+}
+
+impl FromStr for RouteKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, path) = s
+            .split_once(' ')
+            .ok_or_else(|| format!("invalid route key: {}", s))?;
+        let method = match method {
+            "ANY" => None,
+            _ => Some(Method::from_str(method).map_err(|_| format!("invalid route key: {}", s))?),
+        };
+        if !path.starts_with('/') {
+            return Err(format!("invalid route key: {}", s));
+        }
+
+        let mut segments = Vec::new();
+        let parts: Vec<&str> = path[1..].split('/').filter(|p| !p.is_empty()).collect();
+        for (i, part) in parts.iter().enumerate() {
+            let segment = match part
+                .strip_prefix('{')
+                .and_then(|part| part.strip_suffix('}'))
+            {
+                Some(name) => match name.strip_suffix('+') {
+                    Some(name) if i == parts.len() - 1 => Segment::Greedy(name.to_string()),
+                    Some(_) => return Err(format!("greedy path variable must be last: {}", s)),
+                    None => Segment::Param(name.to_string()),
+                },
+                None => Segment::Literal(part.to_string()),
+            };
+            segments.push(segment);
+        }
+        Ok(RouteKey { method, segments })
+    }
+}
+
+impl std::fmt::Display for RouteKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.method {
+            Some(method) => write!(f, "{} {}", method, self.resource()),
+            None => write!(f, "ANY {}", self.resource()),
+        }
+    }
+}
+
+impl RouteKey {
+    /// REST API の resource に相当するパス ("/users/{id}")
+    pub fn resource(&self) -> String {
+        let path: Vec<String> = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(s) => s.clone(),
+                Segment::Param(name) => format!("{{{}}}", name),
+                Segment::Greedy(name) => format!("{{{}+}}", name),
+            })
+            .collect();
+        format!("/{}", path.join("/"))
+    }
+
+    /// マッチした場合はパスパラメーターを返す
+    fn matches(&self, method: &Method, path: &str) -> Option<HashMap<String, String>> {
+        if self.method.as_ref().is_some_and(|m| m != method) {
+            return None;
+        }
+
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        let mut params = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(s) => {
+                    if parts.get(i) != Some(&s.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), decode(parts.get(i)?));
+                }
+                Segment::Greedy(name) => {
+                    if parts.len() <= i {
+                        return None;
+                    }
+                    params.insert(name.clone(), decode(&parts[i..].join("/")));
+                    return Some(params);
+                }
+            }
+        }
+        (parts.len() == self.segments.len()).then_some(params)
+    }
+
+    /// 複数のルートにマッチした場合の優先度。固定のパス > パス変数 > greedy の順で、同じならメソッド指定を優先する
+    fn priority(&self) -> (Vec<u8>, bool) {
+        let segments = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(_) => 2,
+                Segment::Param(_) => 1,
+                Segment::Greedy(_) => 0,
+            })
+            .collect();
+        (segments, self.method.is_some())
+    }
+}
+
+fn decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().into_owned()
+}
+
+pub struct Route {
+    pub key: RouteKey,
+    pub function: Arc<Function>,
+}
+
+/// リクエストにマッチしたルート
+pub struct RouteMatch<'a> {
+    /// マッチしたルート。$default の場合は None
+    pub key: Option<&'a RouteKey>,
+    pub path_parameters: HashMap<String, String>,
+    pub function: &'a Arc<Function>,
+}
+
+/// API Gateway と同じ規則でリクエストを関数に振り分ける
+pub struct RouteTable {
+    routes: Vec<Route>,
+    default: Option<Arc<Function>>,
+}
+
+impl RouteTable {
+    pub fn new(mut routes: Vec<Route>, default: Option<Arc<Function>>) -> Self {
+        routes.sort_by_key(|route| std::cmp::Reverse(route.key.priority()));
+        RouteTable { routes, default }
+    }
+
+    pub fn find(&self, method: &Method, path: &str) -> Option<RouteMatch<'_>> {
+        self.routes
+            .iter()
+            .find_map(|route| {
+                route
+                    .key
+                    .matches(method, path)
+                    .map(|path_parameters| RouteMatch {
+                        key: Some(&route.key),
+                        path_parameters,
+                        function: &route.function,
+                    })
+            })
+            .or_else(|| {
+                self.default.as_ref().map(|function| RouteMatch {
+                    key: None,
+                    path_parameters: HashMap::new(),
+                    function,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::event::{ApiContext, EventFormat};
+    use crate::pool::Pool;
+    use crate::stream::InvokeMode;
+    use std::time::Duration;
+
+    fn key(s: &str) -> RouteKey {
+        s.parse().unwrap()
+    }
+
+    fn function(name: &str) -> Arc<Function> {
+        Arc::new(Function {
+            name: name.to_string(),
+            pool: Arc::new(
+                Pool::new(
+                    vec![Backend::Http {
+                        url: "http://127.0.0.1:9000".to_string(),
+                        client: reqwest::Client::new(),
+                    }],
+                    None,
+                )
+                .unwrap(),
+            ),
+            format: EventFormat::V2,
+            invoke_mode: InvokeMode::Buffered,
+            timeout: Duration::from_secs(3),
+            api: Arc::new(ApiContext::default()),
+        })
+    }
+
+    /// ルートキーと同じ名前の関数を持つルートテーブル
+    fn table(keys: &[&str]) -> RouteTable {
+        let routes = keys
+            .iter()
+            .map(|k| Route {
+                key: key(k),
+                function: function(k),
+            })
+            .collect();
+        RouteTable::new(routes, None)
+    }
+
+    fn find(table: &RouteTable, method: Method, path: &str) -> Option<String> {
+        table
+            .find(&method, path)
+            .map(|route| route.function.name.clone())
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_route_key() {
+        let k = key("GET /users/{id}");
+        assert_eq!(k.method, Some(Method::GET));
+        assert_eq!(
+            k.segments,
+            vec![
+                Segment::Literal("users".to_string()),
+                Segment::Param("id".to_string())
+            ]
+        );
+        assert_eq!(k.to_string(), "GET /users/{id}");
+
+        let k = key("ANY /admin/{proxy+}");
+        assert_eq!(k.method, None);
+        assert_eq!(k.segments[1], Segment::Greedy("proxy".to_string()));
+        assert_eq!(k.to_string(), "ANY /admin/{proxy+}");
+        assert_eq!(k.resource(), "/admin/{proxy+}");
+
+        assert_eq!(key("GET /").resource(), "/");
+    }
+
+    #[test]
+    fn reject_invalid_route_key() {
+        for s in [
+            "GET",
+            "/users",
+            "GET users",
+            "G ET /users",
+            "ANY /{proxy+}/users",
+        ] {
+            assert!(s.parse::<RouteKey>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn match_literal_and_param() {
+        let k = key("GET /users/{id}");
+        assert_eq!(
+            k.matches(&Method::GET, "/users/42"),
+            Some(params(&[("id", "42")]))
+        );
+        assert_eq!(k.matches(&Method::POST, "/users/42"), None);
+        assert_eq!(k.matches(&Method::GET, "/users"), None);
+        assert_eq!(k.matches(&Method::GET, "/users/42/posts"), None);
+        assert_eq!(k.matches(&Method::GET, "/groups/42"), None);
+
+        let k = key("ANY /health");
+        assert_eq!(k.matches(&Method::DELETE, "/health"), Some(HashMap::new()));
+        assert_eq!(k.matches(&Method::GET, "/health/"), Some(HashMap::new()));
+    }
+
+    #[test]
+    fn match_greedy() {
+        let k = key("ANY /admin/{proxy+}");
+        assert_eq!(
+            k.matches(&Method::GET, "/admin/a/b/c"),
+            Some(params(&[("proxy", "a/b/c")]))
+        );
+        assert_eq!(k.matches(&Method::GET, "/admin"), None);
+        assert_eq!(k.matches(&Method::GET, "/admin/"), None);
+
+        let k = key("ANY /{proxy+}");
+        assert_eq!(
+            k.matches(&Method::GET, "/a/b"),
+            Some(params(&[("proxy", "a/b")]))
+        );
+        assert_eq!(k.matches(&Method::GET, "/"), None);
+    }
+
+    #[test]
+    fn decode_path_parameters() {
+        let k = key("GET /files/{name}");
+        assert_eq!(
+            k.matches(&Method::GET, "/files/hello%20world%E3%81%82"),
+            Some(params(&[("name", "hello worldあ")]))
+        );
+        // デコードした / はセグメントを分けない
+        assert_eq!(
+            k.matches(&Method::GET, "/files/a%2Fb"),
+            Some(params(&[("name", "a/b")]))
+        );
+
+        let k = key("GET /files/{proxy+}");
+        assert_eq!(
+            k.matches(&Method::GET, "/files/a%20b/c"),
+            Some(params(&[("proxy", "a b/c")]))
+        );
+    }
+
+    #[test]
+    fn literal_over_param_over_greedy() {
+        let table = table(&["GET /{proxy+}", "GET /users/{id}", "GET /users/me"]);
+        assert_eq!(
+            find(&table, Method::GET, "/users/me").as_deref(),
+            Some("GET /users/me")
+        );
+        assert_eq!(
+            find(&table, Method::GET, "/users/42").as_deref(),
+            Some("GET /users/{id}")
+        );
+        assert_eq!(
+            find(&table, Method::GET, "/users/42/posts").as_deref(),
+            Some("GET /{proxy+}")
+        );
+        assert_eq!(find(&table, Method::GET, "/"), None);
+    }
+
+    #[test]
+    fn method_over_any() {
+        let table = table(&["ANY /users/{id}", "GET /users/{id}", "ANY /users/me"]);
+        assert_eq!(
+            find(&table, Method::GET, "/users/42").as_deref(),
+            Some("GET /users/{id}")
+        );
+        assert_eq!(
+            find(&table, Method::POST, "/users/42").as_deref(),
+            Some("ANY /users/{id}")
+        );
+        // パスの優先度はメソッドより先に比べる
+        assert_eq!(
+            find(&table, Method::GET, "/users/me").as_deref(),
+            Some("ANY /users/me")
+        );
+    }
+
+    #[test]
+    fn fall_back_to_default() {
+        let table = RouteTable::new(
+            vec![Route {
+                key: key("GET /users"),
+                function: function("users"),
+            }],
+            Some(function("default")),
+        );
+        let route = table.find(&Method::GET, "/users").unwrap();
+        assert_eq!(route.key, Some(&key("GET /users")));
+        let route = table.find(&Method::GET, "/other").unwrap();
+        assert_eq!(route.key, None);
+        assert_eq!(route.function.name, "default");
+    }
+}
@@ -0,0 +1,343 @@
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{Path, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{Notify, mpsc, oneshot},
+    time::Instant,
+};
+
+use crate::backend::BackendResponse;
+use crate::error::ProxyError;
+use crate::event::new_trace_id;
+
+struct Invocation {
+    request_id: String,
+    event: Vec<u8>,
+    /// 関数がイベントを受け取ってから応答するまでの制限時間
+    timeout: Duration,
+    /// next でイベントを渡したときに期限を知らせる
+    started: oneshot::Sender<Instant>,
+    responder: oneshot::Sender<BackendResponse>,
+}
+
+/// Lambda Runtime API を実装し、関数に直接イベントを渡す
+pub struct RuntimeApi {
+    function_arn: String,
+    queue_tx: mpsc::UnboundedSender<Invocation>,
+    queue_rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Invocation>>,
+    /// next で渡したがまだ応答がないもの
+    pending: Mutex<HashMap<String, oneshot::Sender<BackendResponse>>>,
+    /// init/error で報告されたエラー。次に next が呼ばれるまで全ての呼び出しをこのエラーで失敗させる
+    init_error: Mutex<Option<Bytes>>,
+    /// タイムアウトした関数のプロセスの再起動の要求
+    recycle: Notify,
+}
+
+impl RuntimeApi {
+    pub fn new(function_arn: String) -> Self {
+        let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+        RuntimeApi {
+            function_arn,
+            queue_tx,
+            queue_rx: tokio::sync::Mutex::new(queue_rx),
+            pending: Mutex::new(HashMap::new()),
+            init_error: Mutex::new(None),
+            recycle: Notify::new(),
+        }
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/2018-06-01/runtime/invocation/next", get(next))
+            .route(
+                "/2018-06-01/runtime/invocation/:request_id/response",
+                post(invocation_response),
+            )
+            .route(
+                "/2018-06-01/runtime/invocation/:request_id/error",
+                post(invocation_error),
+            )
+            .route("/2018-06-01/runtime/init/error", post(init_error))
+            .with_state(self)
+    }
+
+    /// イベントをキューに積み、関数からの応答を待つ。応答と、ボディを読み終えるべき期限を返す。
+    /// 期限は関数が next でイベントを受け取った時点から数えるので、再起動や初期化の時間は含まない。
+    /// ただし timeout を過ぎても受け取られなければ諦める
+    pub async fn invoke(
+        &self,
+        event: Vec<u8>,
+        request_id: &str,
+        timeout: Duration,
+    ) -> Result<(BackendResponse, Instant), ProxyError> {
+        if let Some(error) = self.init_error.lock().unwrap().clone() {
+            return Ok((function_error_response(error), Instant::now() + timeout));
+        }
+
+        let (started, mut started_rx) = oneshot::channel();
+        let (responder, mut rx) = oneshot::channel();
+        let invocation = Invocation {
+            request_id: request_id.to_string(),
+            event,
+            timeout,
+            started,
+            responder,
+        };
+        if self.queue_tx.send(invocation).is_err() {
+            return Err(ProxyError::Backend("runtime api is closed".to_string()));
+        }
+        let dropped =
+            || ProxyError::Backend("invocation was dropped by the runtime api".to_string());
+
+        let deadline = tokio::select! {
+            deadline = &mut started_rx => deadline.map_err(|_| dropped())?,
+            // init/error では渡される前に失敗する
+            response = &mut rx => {
+                return Ok((response.map_err(|_| dropped())?, Instant::now() + timeout));
+            }
+            _ = tokio::time::sleep(timeout) => return Err(ProxyError::Timeout(timeout)),
+        };
+        match tokio::time::timeout_at(deadline, rx).await {
+            Ok(response) => Ok((response.map_err(|_| dropped())?, deadline)),
+            Err(_) => Err(ProxyError::Timeout(timeout)),
+        }
+    }
+
+    /// 関数のプロセスを再起動させる
+    pub fn recycle(&self) {
+        self.recycle.notify_one();
+    }
+
+    /// recycle が呼ばれるまで待つ
+    pub async fn recycle_requested(&self) {
+        self.recycle.notified().await;
+    }
+
+    /// 関数のプロセスが終了したので、処理中の呼び出しを Runtime.ExitError で失敗させる
+    pub fn runtime_exited(&self, status: &str) {
+        let pending: Vec<_> = self.pending.lock().unwrap().drain().collect();
+        pending.into_iter().for_each(|(request_id, responder)| {
+            let error = json!({
+                "errorType": "Runtime.ExitError",
+                "errorMessage": format!(
+                    "RequestId: {} Error: Runtime exited with error: {}",
+                    request_id, status
+                ),
+            });
+            let _ = responder.send(function_error_response(Bytes::from(error.to_string())));
+        });
+    }
+}
+
+async fn next(State(api): State<Arc<RuntimeApi>>) -> Response {
+    let invocation = {
+        let mut queue = api.queue_rx.lock().await;
+        loop {
+            match queue.recv().await {
+                // 待っている間にタイムアウトした呼び出しは渡さない
+                Some(invocation) if invocation.responder.is_closed() => continue,
+                Some(invocation) => break invocation,
+                None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        }
+    };
+    // next を呼べたということは初期化に成功している
+    api.init_error.lock().unwrap().take();
+    // 制限時間はここから数える
+    let _ = invocation.started.send(Instant::now() + invocation.timeout);
+    let deadline_ms = chrono::Utc::now().timestamp_millis() + invocation.timeout.as_millis() as i64;
+    api.pending
+        .lock()
+        .unwrap()
+        .insert(invocation.request_id.clone(), invocation.responder);
+
+    let mut r = Response::new(Body::from(invocation.event));
+    let headers = r.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.insert(
+        "lambda-runtime-aws-request-id",
+        HeaderValue::from_str(&invocation.request_id).unwrap(),
+    );
+    headers.insert("lambda-runtime-deadline-ms", HeaderValue::from(deadline_ms));
+    headers.insert(
+        "lambda-runtime-invoked-function-arn",
+        HeaderValue::from_str(&api.function_arn).unwrap(),
+    );
+    headers.insert(
+        "lambda-runtime-trace-id",
+        HeaderValue::from_str(&new_trace_id()).unwrap(),
+    );
+    r
+}
+
+async fn invocation_response(
+    State(api): State<Arc<RuntimeApi>>,
+    Path(request_id): Path<String>,
+    req: Request,
+) -> Response {
+    let Some(responder) = api.pending.lock().unwrap().remove(&request_id) else {
+        return invalid_request_id();
+    };
+
+    let mut headers = HeaderMap::new();
+    if let Some(content_type) = req.headers().get(header::CONTENT_TYPE) {
+        headers.insert(header::CONTENT_TYPE, content_type.clone());
+    }
+
+    // ストリーミングの場合もあるので、ボディをクライアントが読み終えるまでこのリクエストを保持する
+    let (done_tx, done_rx) = oneshot::channel();
+    let guard = DoneGuard(Some(done_tx));
+    let body = futures_util::stream::unfold(
+        (req.into_body().into_data_stream(), guard),
+        |(mut stream, guard)| async move {
+            let chunk = stream.next().await?;
+            Some((chunk, (stream, guard)))
+        },
+    );
+    let response = BackendResponse {
+        status: StatusCode::OK,
+        headers,
+        body: Body::from_stream(body),
+    };
+    if responder.send(response).is_ok() {
+        let _ = done_rx.await;
+    }
+    accepted()
+}
+
+async fn invocation_error(
+    State(api): State<Arc<RuntimeApi>>,
+    Path(request_id): Path<String>,
+    body: Bytes,
+) -> Response {
+    let Some(responder) = api.pending.lock().unwrap().remove(&request_id) else {
+        return invalid_request_id();
+    };
+    let _ = responder.send(function_error_response(body));
+    accepted()
+}
+
+async fn init_error(State(api): State<Arc<RuntimeApi>>, body: Bytes) -> Response {
+    tracing::error!(
+        "function initialization failed: {}",
+        String::from_utf8_lossy(&body)
+    );
+    *api.init_error.lock().unwrap() = Some(body.clone());
+
+    // 待機中の呼び出しも全て失敗させる
+    let mut queue = api.queue_rx.lock().await;
+    while let Ok(invocation) = queue.try_recv() {
+        let _ = invocation
+            .responder
+            .send(function_error_response(body.clone()));
+    }
+    accepted()
+}
+
+/// 受け取ったボディが全て読まれるか破棄されたときに通知する
+struct DoneGuard(Option<oneshot::Sender<()>>);
+
+impl Drop for DoneGuard {
+    fn drop(&mut self) {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+fn function_error_response(error: Bytes) -> BackendResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.insert(
+        "x-amz-function-error",
+        HeaderValue::from_static("Unhandled"),
+    );
+    BackendResponse {
+        status: StatusCode::OK,
+        headers,
+        body: Body::from(error),
+    }
+}
+
+fn accepted() -> Response {
+    (StatusCode::ACCEPTED, Json(json!({ "status": "OK" }))).into_response()
+}
+
+fn invalid_request_id() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "errorMessage": "Invalid request ID",
+            "errorType": "InvalidRequestID",
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn timeout_starts_when_event_is_received() {
+        let api = Arc::new(RuntimeApi::new("arn".to_string()));
+        let timeout = Duration::from_millis(300);
+        let invoke = tokio::spawn({
+            let api = api.clone();
+            async move { api.invoke(b"{}".to_vec(), "id", timeout).await }
+        });
+
+        // 再起動中などで関数がイベントを受け取るのが遅れても、その分は制限時間に含めない
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let received = Instant::now();
+        next(State(api.clone())).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let responder = api.pending.lock().unwrap().remove("id").unwrap();
+        let _ = responder.send(function_error_response(Bytes::new()));
+
+        let (_, deadline) = invoke.await.unwrap().ok().unwrap();
+        assert!(deadline >= received + timeout);
+    }
+
+    #[tokio::test]
+    async fn timeout_after_event_is_received() {
+        let api = Arc::new(RuntimeApi::new("arn".to_string()));
+        let timeout = Duration::from_millis(100);
+        let invoke = tokio::spawn({
+            let api = api.clone();
+            async move { api.invoke(b"{}".to_vec(), "id", timeout).await }
+        });
+
+        next(State(api.clone())).await;
+        let result = invoke.await.unwrap();
+        assert!(matches!(result, Err(ProxyError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn timeout_without_next() {
+        let api = RuntimeApi::new("arn".to_string());
+        let result = api
+            .invoke(b"{}".to_vec(), "id", Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(ProxyError::Timeout(_))));
+    }
+}
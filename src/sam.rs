@@ -0,0 +1,141 @@
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::event::EventFormat;
+use crate::route::RouteKey;
+use crate::stream::InvokeMode;
+
+/// SAM テンプレートの AWS::Serverless::Function
+pub struct SamFunction {
+    pub logical_id: String,
+    /// CodeUri をテンプレートのディレクトリからの相対パスとして解決したもの
+    pub code_dir: Option<PathBuf>,
+    pub runtime: Option<String>,
+    pub memory_size: Option<u32>,
+    /// Timeout (秒)
+    pub timeout: Option<u64>,
+    pub environment: HashMap<String, String>,
+    /// FunctionUrlConfig がある場合の呼び出しモード
+    pub function_url: Option<InvokeMode>,
+    pub events: Vec<SamEvent>,
+}
+
+/// HttpApi / Api イベント
+pub struct SamEvent {
+    /// None は $default ルート
+    pub route_key: Option<RouteKey>,
+    pub format: EventFormat,
+}
+
+pub fn load(path: &Path) -> Result<Vec<SamFunction>, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let template = crate::yaml::parse(&source).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    let parameters = &template["Parameters"];
+    let globals = &template["Globals"]["Function"];
+    let Some(resources) = template["Resources"].as_object() else {
+        return Err(format!("{}: Resources is not defined", path.display()));
+    };
+
+    let mut functions = Vec::new();
+    for (logical_id, resource) in resources {
+        if resource["Type"] != "AWS::Serverless::Function" {
+            continue;
+        }
+        let properties = &resource["Properties"];
+        // Globals の値を関数ごとの値で上書きする
+        let property = |name: &str| -> Option<&Value> {
+            Some(&properties[name])
+                .filter(|v| !v.is_null())
+                .or(Some(&globals[name]).filter(|v| !v.is_null()))
+        };
+
+        let mut environment = HashMap::new();
+        for variables in [
+            &globals["Environment"]["Variables"],
+            &properties["Environment"]["Variables"],
+        ] {
+            for (name, value) in variables.as_object().into_iter().flatten() {
+                match resolve_string(value, parameters) {
+                    Some(value) => {
+                        environment.insert(name.clone(), value);
+                    }
+                    None => tracing::warn!(
+                        "{}: cannot resolve environment variable {}",
+                        logical_id,
+                        name
+                    ),
+                }
+            }
+        }
+
+        let function_url =
+            property("FunctionUrlConfig").map(|config| match config["InvokeMode"].as_str() {
+                Some("RESPONSE_STREAM") => InvokeMode::ResponseStream,
+                _ => InvokeMode::Buffered,
+            });
+
+        let mut events = Vec::new();
+        for (name, event) in properties["Events"].as_object().into_iter().flatten() {
+            let format = match event["Type"].as_str() {
+                Some("HttpApi") => match event["Properties"]["PayloadFormatVersion"].as_str() {
+                    Some("1.0") => EventFormat::V1,
+                    _ => EventFormat::V2,
+                },
+                Some("Api") => EventFormat::V1,
+                _ => continue,
+            };
+            let path = event["Properties"]["Path"].as_str();
+            let method = event["Properties"]["Method"]
+                .as_str()
+                .map(|m| m.to_ascii_uppercase())
+                .unwrap_or_else(|| "ANY".to_string());
+            let route_key = match path {
+                Some(path) => Some(
+                    format!("{} {}", method, path)
+                        .parse()
+                        .map_err(|e| format!("{}.Events.{}: {}", logical_id, name, e))?,
+                ),
+                None => None,
+            };
+            events.push(SamEvent { route_key, format });
+        }
+
+        functions.push(SamFunction {
+            logical_id: logical_id.clone(),
+            code_dir: property("CodeUri")
+                .and_then(|v| v.as_str())
+                .map(|uri| base_dir.join(uri)),
+            runtime: property("Runtime")
+                .and_then(|v| v.as_str())
+                .map(String::from),
+            memory_size: property("MemorySize")
+                .and_then(|v| v.as_u64())
+                .map(|v| v as u32),
+            timeout: property("Timeout").and_then(|v| v.as_u64()),
+            environment,
+            function_url,
+            events,
+        });
+    }
+    Ok(functions)
+}
+
+/// 文字列・数値・真偽値と、Parameters の Default を参照する Ref だけ解決する
+fn resolve_string(value: &Value, parameters: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Object(map) => {
+            let name = map.get("Ref")?.as_str()?;
+            resolve_string(&parameters[name]["Default"], parameters)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,260 @@
+use axum::{
+    body::Body,
+    http::{Response, StatusCode, header},
+};
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::{collections::HashMap, str::FromStr};
+
+use crate::backend::BackendResponse;
+use crate::error::ProxyError;
+use crate::response::insert_headers;
+
+/// Function URL の呼び出しモード
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvokeMode {
+    Buffered,
+    ResponseStream,
+}
+
+impl FromStr for InvokeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "BUFFERED" => Ok(InvokeMode::Buffered),
+            "RESPONSE_STREAM" => Ok(InvokeMode::ResponseStream),
+            _ => Err(format!("unknown invoke mode: {}", s)),
+        }
+    }
+}
+
+/// ストリーミングレスポンスで HTTP のメタデータを送る場合の Content-Type
+const HTTP_INTEGRATION_CONTENT_TYPE: &str = "application/vnd.awslambda.http-integration-response";
+
+/// プレリュードとボディの区切り
+const PRELUDE_SEPARATOR: [u8; 8] = [0; 8];
+
+/// ストリーミングレスポンスの先頭に付く JSON
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Prelude {
+    status_code: Option<u16>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    multi_value_headers: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    cookies: Vec<String>,
+}
+
+/// ボディを limit バイトまで読み込む。超えた場合は None
+pub async fn read_body(body: Body, limit: usize) -> Result<Option<Bytes>, axum::Error> {
+    let mut stream = body.into_data_stream();
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if buf.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Some(buf.freeze()))
+}
+
+/// limit バイトを超えたところでエラーにして打ち切る
+fn limit_body(body: Body, limit: usize) -> Body {
+    let stream = futures_util::stream::unfold(
+        Some((body.into_data_stream(), 0)),
+        move |state| async move {
+            let (mut stream, size) = state?;
+            let chunk = match stream.next().await? {
+                Ok(chunk) => chunk,
+                Err(e) => return Some((Err(e), None)),
+            };
+            let size = size + chunk.len();
+            if size > limit {
+                tracing::error!(
+                    "Response payload size exceeded maximum allowed payload streaming limit ({} bytes).",
+                    limit
+                );
+                let error = std::io::Error::other("response payload too large");
+                return Some((Err(axum::Error::new(error)), None));
+            }
+            Some((Ok(chunk), Some((stream, size))))
+        },
+    );
+    Body::from_stream(stream)
+}
+
+/// バックエンドのレスポンスをバッファリングせずにクライアントへ転送する。
+/// プレリュードを除くボディが limit バイトを超えた場合は打ち切る
+pub async fn stream_response(
+    response: BackendResponse,
+    limit: usize,
+) -> Result<Response<Body>, ProxyError> {
+    let content_type = response.headers.get(header::CONTENT_TYPE).cloned();
+    if content_type.as_ref().map(|v| v.as_bytes()) != Some(HTTP_INTEGRATION_CONTENT_TYPE.as_bytes())
+    {
+        // メタデータなしの場合はそのまま 200 で返す
+        let mut r = Response::new(limit_body(response.body, limit));
+        r.headers_mut().insert(
+            header::CONTENT_TYPE,
+            content_type
+                .unwrap_or_else(|| header::HeaderValue::from_static("application/octet-stream")),
+        );
+        return Ok(r);
+    }
+
+    let mut stream = response.body.into_data_stream();
+    let mut buf = BytesMut::new();
+    let pos = loop {
+        if let Some(pos) = buf
+            .windows(PRELUDE_SEPARATOR.len())
+            .position(|w| w == PRELUDE_SEPARATOR)
+        {
+            break pos;
+        }
+        match stream.next().await {
+            Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+            Some(Err(e)) => return Err(ProxyError::from_body_error(e)),
+            None => {
+                return Err(ProxyError::InvalidResponse(
+                    "response stream ended before prelude separator".to_string(),
+                ));
+            }
+        }
+    };
+    let prelude: Prelude = serde_json::from_slice(&buf[..pos])
+        .map_err(|e| ProxyError::InvalidResponse(format!("invalid prelude: {}", e)))?;
+    let rest = buf.split_off(pos + PRELUDE_SEPARATOR.len()).freeze();
+
+    // 区切りの後ろに続けて読み込んでしまった分を先に流す
+    let head = futures_util::stream::iter((!rest.is_empty()).then_some(Ok(rest)));
+    let mut r = Response::new(limit_body(Body::from_stream(head.chain(stream)), limit));
+    *r.status_mut() = StatusCode::from_u16(prelude.status_code.unwrap_or(200))
+        .map_err(|e| ProxyError::InvalidResponse(e.to_string()))?;
+    insert_headers(
+        r.headers_mut(),
+        prelude.headers,
+        prelude.multi_value_headers,
+        prelude.cookies,
+    )?;
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend_response(content_type: Option<&str>, chunks: &[&[u8]]) -> BackendResponse {
+        let mut headers = header::HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        }
+        let chunks: Vec<Result<Bytes, std::io::Error>> = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        BackendResponse {
+            status: StatusCode::OK,
+            headers,
+            body: Body::from_stream(futures_util::stream::iter(chunks)),
+        }
+    }
+
+    async fn stream(response: BackendResponse) -> Result<Response<Body>, ProxyError> {
+        stream_response(response, 1024).await
+    }
+
+    async fn body(response: Response<Body>) -> Result<Bytes, axum::Error> {
+        axum::body::to_bytes(response.into_body(), usize::MAX).await
+    }
+
+    #[tokio::test]
+    async fn prelude_split_across_chunks() {
+        let r = stream(backend_response(
+            Some(HTTP_INTEGRATION_CONTENT_TYPE),
+            &[
+                b"{\"statusCode\":201,\"head",
+                b"ers\":{\"x-a\":\"1\"},\"cookies\":[\"a=1\",\"b=2\"]}\0\0\0",
+                b"\0\0\0\0\0hel",
+                b"lo",
+            ],
+        ))
+        .await
+        .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(r.status(), 201);
+        assert_eq!(r.headers()["x-a"], "1");
+        assert_eq!(r.headers().get_all("set-cookie").iter().count(), 2);
+        assert_eq!(body(r).await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn body_in_same_chunk_as_separator() {
+        let r = stream(backend_response(
+            Some(HTTP_INTEGRATION_CONTENT_TYPE),
+            &[b"{}\0\0\0\0\0\0\0\0body", b" rest"],
+        ))
+        .await
+        .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(r.status(), 200);
+        assert_eq!(body(r).await.unwrap(), "body rest");
+    }
+
+    #[tokio::test]
+    async fn stream_ends_before_separator() {
+        let e = stream(backend_response(
+            Some(HTTP_INTEGRATION_CONTENT_TYPE),
+            &[b"{\"statusCode\":200}\0\0\0"],
+        ))
+        .await
+        .err()
+        .unwrap();
+        assert!(matches!(e, ProxyError::InvalidResponse(_)), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn invalid_prelude() {
+        let e = stream(backend_response(
+            Some(HTTP_INTEGRATION_CONTENT_TYPE),
+            &[b"not json\0\0\0\0\0\0\0\0"],
+        ))
+        .await
+        .err()
+        .unwrap();
+        assert!(matches!(e, ProxyError::InvalidResponse(_)), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn without_prelude() {
+        let r = stream(backend_response(Some("text/plain"), &[b"a", b"b"]))
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(r.status(), 200);
+        assert_eq!(r.headers()["content-type"], "text/plain");
+        assert_eq!(body(r).await.unwrap(), "ab");
+
+        let r = stream(backend_response(None, &[b"a"]))
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(r.headers()["content-type"], "application/octet-stream");
+    }
+
+    #[tokio::test]
+    async fn body_over_limit_is_cut() {
+        let chunk = [b'x'; 600];
+        let r = stream(backend_response(None, &[&chunk, &chunk]))
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+        assert!(body(r).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_body_limit() {
+        let body = Body::from("hello");
+        assert_eq!(read_body(body, 5).await.unwrap().unwrap(), "hello");
+        assert!(read_body(Body::from("hello"), 4).await.unwrap().is_none());
+    }
+}
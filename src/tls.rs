@@ -0,0 +1,100 @@
+use axum::{Router, extract::ConnectInfo, extract::Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use std::sync::Arc;
+use tokio::{net::TcpListener, sync::watch};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+use tower::ServiceExt;
+
+use crate::config::TlsConfig;
+
+/// 証明書と秘密鍵 (PEM) を読み込む。ALPN で HTTP/2 と HTTP/1.1 を提示する
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let certificates = CertificateDer::pem_file_iter(&config.certificate)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{}: {}", config.certificate.display(), e))?;
+    let private_key = PrivateKeyDer::from_pem_file(&config.private_key)
+        .map_err(|e| format!("{}: {}", config.private_key.display(), e))?;
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| {
+            builder
+                .with_no_client_auth()
+                .with_single_cert(certificates, private_key)
+        })
+        .map_err(|e| format!("{}: {}", config.certificate.display(), e))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// TLS で待ち受ける。stop を受けたら新しい接続を受け付けず、処理中の接続が終わるのを待つ
+pub async fn serve(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    app: Router,
+    mut stop: watch::Receiver<bool>,
+) {
+    let graceful = GracefulShutdown::new();
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = stop.wait_for(|stop| *stop) => break,
+        };
+        let acceptor = acceptor.clone();
+        // axum::serve の into_make_service_with_connect_info と同じく ConnectInfo を渡す
+        let service = app.clone().map_request(move |mut req: Request<_>| {
+            req.extensions_mut().insert(ConnectInfo(peer));
+            req
+        });
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(
+                TokioIo::new(stream),
+                TowerToHyperService::new(service),
+            );
+            if let Err(e) = watcher.watch(connection).await {
+                tracing::debug!("connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+    graceful.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_certificate() {
+        let config = TlsConfig {
+            certificate: "/nonexistent/cert.pem".into(),
+            private_key: "/nonexistent/key.pem".into(),
+        };
+        let e = acceptor(&config).err().unwrap();
+        assert!(e.starts_with("/nonexistent/cert.pem: "), "{}", e);
+    }
+}
@@ -0,0 +1,732 @@
+//! SAM テンプレートや設定ファイルを読むための最小限の YAML パーサー
+//!
+//! ブロック形式のマッピング・シーケンス、フロー形式 (`[a, b]`, `{a: b}`)、ブロックスカラー (`|`, `>`)、
+//! CloudFormation の短縮形のタグ (`!Ref`, `!GetAtt`, `!Sub` など) に対応する。
+//! アンカーやエイリアス、複数ドキュメントには対応せず、アンカーとエイリアスはエラーにする。
+
+use serde_json::{Map, Value};
+
+/// パースエラー。line は 1 始まり
+#[derive(Debug)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub fn parse(source: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        lines: source
+            .lines()
+            .filter(|line| !line.starts_with("---") && !line.starts_with("..."))
+            .map(|line| line.trim_end().to_string())
+            .collect(),
+        pos: 0,
+    };
+    parser.skip_blank();
+    if parser.pos >= parser.lines.len() {
+        return Ok(Value::Null);
+    }
+    let indent = parser.indent();
+    let value = parser.parse_block(indent)?;
+    parser.skip_blank();
+    if parser.pos < parser.lines.len() {
+        return Err(parser.error("unexpected indentation"));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    lines: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> Error {
+        Error {
+            line: self.pos + 1,
+            message: message.to_string(),
+        }
+    }
+
+    /// 空行とコメントだけの行を読み飛ばす
+    fn skip_blank(&mut self) {
+        while self.pos < self.lines.len() {
+            let line = self.lines[self.pos].trim_start();
+            if !line.is_empty() && !line.starts_with('#') {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn indent(&self) -> usize {
+        let line = &self.lines[self.pos];
+        line.len() - line.trim_start_matches(' ').len()
+    }
+
+    fn content(&self) -> String {
+        strip_comment(self.lines[self.pos].trim_start())
+    }
+
+    fn is_sequence_item(&self) -> bool {
+        let content = self.content();
+        content == "-" || content.starts_with("- ")
+    }
+
+    fn parse_block(&mut self, indent: usize) -> Result<Value, Error> {
+        if self.is_sequence_item() {
+            self.parse_sequence(indent)
+        } else {
+            self.parse_mapping(indent)
+        }
+    }
+
+    fn parse_sequence(&mut self, indent: usize) -> Result<Value, Error> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.pos >= self.lines.len() || self.indent() != indent || !self.is_sequence_item() {
+                break;
+            }
+            let content = self.content();
+            let rest = content[1..].trim_start();
+            if rest.is_empty() {
+                self.pos += 1;
+                items.push(self.parse_nested(indent)?);
+            } else if split_key_value(rest).is_some() || rest.starts_with("- ") {
+                // "- key: value" は "- " を空白に置き換えて、一段深いブロックとして読む
+                let offset = content.len() - rest.len();
+                let line = &mut self.lines[self.pos];
+                line.replace_range(indent..indent + offset, &" ".repeat(offset));
+                items.push(self.parse_block(indent + offset)?);
+            } else {
+                let rest = rest.to_string();
+                self.pos += 1;
+                items.push(self.parse_inline(&rest, indent)?);
+            }
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn parse_mapping(&mut self, indent: usize) -> Result<Value, Error> {
+        let mut map = Map::new();
+        loop {
+            self.skip_blank();
+            if self.pos >= self.lines.len() || self.indent() != indent || self.is_sequence_item() {
+                break;
+            }
+            let content = self.content();
+            let (key, value) = split_key_value(&content)
+                .ok_or_else(|| self.error(&format!("expected 'key: value': {}", content)))?;
+            let key = match parse_scalar(key) {
+                Value::String(s) => s,
+                other => other.to_string(),
+            };
+            let value = value.to_string();
+            self.pos += 1;
+
+            let value = if value.is_empty() {
+                self.skip_blank();
+                // キーと同じインデントのシーケンスも値として扱う
+                if self.pos < self.lines.len() && self.indent() == indent && self.is_sequence_item()
+                {
+                    self.parse_sequence(indent)?
+                } else {
+                    self.parse_nested(indent)?
+                }
+            } else {
+                self.parse_inline(&value, indent)?
+            };
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+
+    /// 次の行が indent より深ければそのブロックを、そうでなければ null を返す
+    fn parse_nested(&mut self, indent: usize) -> Result<Value, Error> {
+        self.skip_blank();
+        if self.pos < self.lines.len() && self.indent() > indent {
+            let indent = self.indent();
+            self.parse_block(indent)
+        } else {
+            Ok(Value::Null)
+        }
+    }
+
+    /// 行内の値を読んだ後のエラー。その行は読み終えているので直前の行を指す
+    fn inline_error(&self, message: &str) -> Error {
+        Error {
+            line: self.pos,
+            message: message.to_string(),
+        }
+    }
+
+    /// 行内の値。ブロックスカラーやタグの後ろのブロック、閉じていないフロー形式は後続の行も読む
+    fn parse_inline(&mut self, value: &str, indent: usize) -> Result<Value, Error> {
+        if value.starts_with('&') || value.starts_with('*') {
+            return Err(self.inline_error("anchors and aliases are not supported"));
+        }
+        if let Some(tagged) = value.strip_prefix('!') {
+            let (tag, rest) = tagged.split_once(' ').unwrap_or((tagged, ""));
+            let rest = rest.trim();
+            let value = if rest.is_empty() {
+                self.parse_nested(indent)?
+            } else {
+                self.parse_inline(rest, indent)?
+            };
+            return Ok(apply_tag(tag, value));
+        }
+        if value.starts_with('|') || value.starts_with('>') {
+            return Ok(Value::String(self.parse_block_scalar(value, indent)));
+        }
+        if value.starts_with('[') || value.starts_with('{') {
+            let start = self.pos;
+            let mut value = value.to_string();
+            while flow_depth(&value) > 0 && self.pos < self.lines.len() {
+                value.push(' ');
+                value.push_str(&strip_comment(self.lines[self.pos].trim()));
+                self.pos += 1;
+            }
+            let mut flow = FlowParser {
+                chars: value.chars().collect(),
+                pos: 0,
+            };
+            let parsed = flow.parse_value().map_err(|message| Error {
+                line: start,
+                message,
+            })?;
+            flow.skip_spaces();
+            if flow.peek().is_some() {
+                return Err(Error {
+                    line: start,
+                    message: "unexpected characters after flow collection".to_string(),
+                });
+            }
+            return Ok(parsed);
+        }
+        Ok(parse_scalar(value))
+    }
+
+    fn parse_block_scalar(&mut self, header: &str, indent: usize) -> String {
+        let folded = header.starts_with('>');
+        let chomping = header[1..].trim();
+
+        let mut lines = Vec::new();
+        let mut block_indent = None;
+        while self.pos < self.lines.len() {
+            let line = &self.lines[self.pos];
+            let line_indent = line.len() - line.trim_start_matches(' ').len();
+            if line.trim().is_empty() {
+                lines.push(String::new());
+            } else if line_indent > indent {
+                let block_indent = *block_indent.get_or_insert(line_indent);
+                lines.push(
+                    line.get(block_indent.min(line_indent)..)
+                        .unwrap_or("")
+                        .to_string(),
+                );
+            } else {
+                break;
+            }
+            self.pos += 1;
+        }
+        let trailing_blank = lines.iter().rev().take_while(|l| l.is_empty()).count();
+        let content_lines = &lines[..lines.len() - trailing_blank];
+
+        let mut text = if folded {
+            let mut text = String::new();
+            for (i, line) in content_lines.iter().enumerate() {
+                if i > 0 {
+                    // 空行はそれぞれ改行になり、直前の改行は取り除く
+                    if line.is_empty() {
+                        text.push('\n');
+                    } else if !content_lines[i - 1].is_empty() {
+                        text.push(' ');
+                    }
+                }
+                text.push_str(line);
+            }
+            text
+        } else {
+            content_lines.join("\n")
+        };
+        match chomping {
+            "-" => {}
+            "+" => {
+                text.push('\n');
+                text.push_str(&"\n".repeat(trailing_blank));
+            }
+            _ => {
+                if !content_lines.is_empty() {
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+}
+
+/// CloudFormation の短縮形のタグを完全な関数名の形に変換する
+fn apply_tag(tag: &str, value: Value) -> Value {
+    match tag {
+        "Ref" => serde_json::json!({ "Ref": value }),
+        "GetAtt" => match value {
+            Value::String(s) => {
+                let parts: Vec<&str> = s.splitn(2, '.').collect();
+                serde_json::json!({ "Fn::GetAtt": parts })
+            }
+            other => serde_json::json!({ "Fn::GetAtt": other }),
+        },
+        // !!str などの標準のタグはそのまま値を返す
+        tag if tag.starts_with('!') => value,
+        tag => serde_json::json!({ format!("Fn::{}", tag): value }),
+    }
+}
+
+/// "key: value" を分割する。引用符やフロー形式の中のコロンは無視する
+fn split_key_value(content: &str) -> Option<(&str, &str)> {
+    let mut quote = None;
+    let mut depth = 0;
+    for (i, c) in content.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if i == 0 => quote = Some(c),
+            (None, '[' | '{') => depth += 1,
+            (None, ']' | '}') => depth -= 1,
+            (None, ':') if depth == 0 => {
+                let rest = &content[i + 1..];
+                if rest.is_empty() || rest.starts_with(' ') {
+                    return Some((content[..i].trim(), rest.trim()));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// 引用符の外で閉じていない [ と { の数
+fn flow_depth(content: &str) -> i32 {
+    let mut quote = None;
+    let mut depth = 0;
+    let mut prev = ' ';
+    for c in content.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if matches!(prev, ' ' | '[' | '{' | ',' | ':') => quote = Some(c),
+            (None, '[' | '{') => depth += 1,
+            (None, ']' | '}') => depth -= 1,
+            _ => {}
+        }
+        prev = c;
+    }
+    depth
+}
+
+/// 引用符の外にある " #" 以降をコメントとして取り除く
+fn strip_comment(content: &str) -> String {
+    let mut quote = None;
+    let mut prev = ' ';
+    for (i, c) in content.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if prev == ' ' || prev == '[' || prev == '{' || prev == ',' => {
+                quote = Some(c)
+            }
+            (None, '#') if prev == ' ' || prev == '\t' => {
+                return content[..i].trim_end().to_string();
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    content.to_string()
+}
+
+fn parse_scalar(s: &str) -> Value {
+    let s = s.trim();
+    if let Some(inner) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        return Value::String(unescape_double_quoted(inner));
+    }
+    if let Some(inner) = s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+        return Value::String(inner.replace("''", "'"));
+    }
+    match s {
+        "" | "~" | "null" | "Null" | "NULL" => return Value::Null,
+        "true" | "True" | "TRUE" => return Value::Bool(true),
+        "false" | "False" | "FALSE" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Ok(i) = s.parse::<i64>() {
+        return Value::from(i);
+    }
+    if s.chars().any(|c| c.is_ascii_digit())
+        && let Ok(f) = s.parse::<f64>()
+        && f.is_finite()
+    {
+        return Value::from(f);
+    }
+    Value::String(s.to_string())
+}
+
+fn unescape_double_quoted(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// フロー形式 ([a, b], {a: b}) のパーサー
+struct FlowParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl FlowParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        self.skip_spaces();
+        match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_spaces();
+                    if self.peek() == Some(']') {
+                        self.pos += 1;
+                        break;
+                    }
+                    items.push(self.parse_value()?);
+                    self.skip_spaces();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some(']') => {}
+                        _ => return Err("expected ',' or ']'".to_string()),
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut map = Map::new();
+                loop {
+                    self.skip_spaces();
+                    if self.peek() == Some('}') {
+                        self.pos += 1;
+                        break;
+                    }
+                    let key = match self.parse_scalar_token(true)? {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    self.skip_spaces();
+                    if self.peek() != Some(':') {
+                        return Err("expected ':'".to_string());
+                    }
+                    self.pos += 1;
+                    let value = self.parse_value()?;
+                    map.insert(key, value);
+                    self.skip_spaces();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some('}') => {}
+                        _ => return Err("expected ',' or '}'".to_string()),
+                    }
+                }
+                Ok(Value::Object(map))
+            }
+            Some('!') => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().is_some_and(|c| !c.is_whitespace()) {
+                    self.pos += 1;
+                }
+                let tag: String = self.chars[start..self.pos].iter().collect();
+                let value = self.parse_value()?;
+                Ok(apply_tag(&tag, value))
+            }
+            Some(_) => self.parse_scalar_token(false),
+            None => Err("unexpected end of flow collection".to_string()),
+        }
+    }
+
+    fn parse_scalar_token(&mut self, is_key: bool) -> Result<Value, String> {
+        self.skip_spaces();
+        if let Some('&' | '*') = self.peek() {
+            return Err("anchors and aliases are not supported".to_string());
+        }
+        if let Some(q @ ('"' | '\'')) = self.peek() {
+            let start = self.pos;
+            self.pos += 1;
+            while let Some(c) = self.peek() {
+                self.pos += 1;
+                if c == '\\' && q == '"' {
+                    self.pos += 1;
+                } else if c == '\'' && q == '\'' && self.peek() == Some('\'') {
+                    // '' は ' のエスケープ
+                    self.pos += 1;
+                } else if c == q {
+                    break;
+                }
+            }
+            let token: String = self.chars[start..self.pos.min(self.chars.len())]
+                .iter()
+                .collect();
+            return Ok(parse_scalar(&token));
+        }
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == ',' || c == ']' || c == '}' || (is_key && c == ':') {
+                break;
+            }
+            self.pos += 1;
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+        Ok(parse_scalar(&token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse_ok(source: &str) -> Value {
+        parse(source).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[test]
+    fn block_mapping_and_sequence() {
+        let value = parse_ok(
+            "name: app\n\
+             count: 3\n\
+             ratio: 0.5\n\
+             enabled: true\n\
+             empty:\n\
+             nested:\n  key: value\n  list:\n    - a\n    - 2\n\
+             same_indent:\n- x\n- y\n",
+        );
+        assert_eq!(
+            value,
+            json!({
+                "name": "app",
+                "count": 3,
+                "ratio": 0.5,
+                "enabled": true,
+                "empty": null,
+                "nested": { "key": "value", "list": ["a", 2] },
+                "same_indent": ["x", "y"],
+            })
+        );
+    }
+
+    #[test]
+    fn sequence_of_mappings() {
+        let value = parse_ok(
+            "routes:\n  - route: GET /users/{id}\n    function: users\n  - route: $default\n    function: admin\n",
+        );
+        assert_eq!(
+            value,
+            json!({
+                "routes": [
+                    { "route": "GET /users/{id}", "function": "users" },
+                    { "route": "$default", "function": "admin" },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn nested_sequences() {
+        let value = parse_ok("- - a\n  - b\n- c\n");
+        assert_eq!(value, json!([["a", "b"], "c"]));
+    }
+
+    #[test]
+    fn flow_collections() {
+        let value = parse_ok("list: [a, 1, \"b, c\"]\nmap: {x: 1, y: [2, 3]}\nempty: []\n");
+        assert_eq!(
+            value,
+            json!({
+                "list": ["a", 1, "b, c"],
+                "map": { "x": 1, "y": [2, 3] },
+                "empty": [],
+            })
+        );
+    }
+
+    #[test]
+    fn multi_line_flow_collections() {
+        let value =
+            parse_ok("a: [\n  1,\n  2  # comment\n]\nb: {\n  x: 1,\n  y: 'it''s'\n}\nc: 3\n");
+        assert_eq!(
+            value,
+            json!({ "a": [1, 2], "b": { "x": 1, "y": "it's" }, "c": 3 })
+        );
+    }
+
+    #[test]
+    fn literal_block_scalars() {
+        let value =
+            parse_ok("keep: |+\n  a\n  b\n\nclip: |\n  a\n\n  b\n\nstrip: |-\n  a\n  b\nnext: 1\n");
+        assert_eq!(
+            value,
+            json!({
+                "keep": "a\nb\n\n",
+                "clip": "a\n\nb\n",
+                "strip": "a\nb",
+                "next": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn folded_block_scalars() {
+        let value = parse_ok("text: >\n  sample app\n  folded text\n\n  new paragraph\n");
+        assert_eq!(
+            value,
+            json!({ "text": "sample app folded text\nnew paragraph\n" })
+        );
+    }
+
+    #[test]
+    fn cloudformation_tags() {
+        let value = parse_ok(
+            "ref: !Ref Stage\n\
+             att: !GetAtt Table.Arn\n\
+             sub: !Sub \"${AWS::StackName}-users\"\n\
+             join: !Join [\"-\", [a, !Ref Stage]]\n\
+             nested: !If\n  - IsProd\n  - prod\n  - dev\n\
+             str: !!str 123\n",
+        );
+        assert_eq!(
+            value,
+            json!({
+                "ref": { "Ref": "Stage" },
+                "att": { "Fn::GetAtt": ["Table", "Arn"] },
+                "sub": { "Fn::Sub": "${AWS::StackName}-users" },
+                "join": { "Fn::Join": ["-", ["a", { "Ref": "Stage" }]] },
+                "nested": { "Fn::If": ["IsProd", "prod", "dev"] },
+                "str": 123,
+            })
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let value = parse_ok(
+            "# header\n---\na: 1 # trailing\n\n  # indented comment\nb: \"x # not a comment\"\nc: x#y\n",
+        );
+        assert_eq!(
+            value,
+            json!({ "a": 1, "b": "x # not a comment", "c": "x#y" })
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        let value = parse_ok(
+            "double: \"a\\tb\\n\\\"c\\\"\"\n\
+             single: 'it''s'\n\
+             number: \"123\"\n\
+             bool: 'true'\n\
+             null_string: \"null\"\n\
+             \"quoted key\": v\n\
+             colon: \"a: b\"\n\
+             url: http://127.0.0.1:9000\n",
+        );
+        assert_eq!(
+            value,
+            json!({
+                "double": "a\tb\n\"c\"",
+                "single": "it's",
+                "number": "123",
+                "bool": "true",
+                "null_string": "null",
+                "quoted key": "v",
+                "colon": "a: b",
+                "url": "http://127.0.0.1:9000",
+            })
+        );
+    }
+
+    #[test]
+    fn empty_document() {
+        assert_eq!(parse_ok(""), Value::Null);
+        assert_eq!(parse_ok("# only a comment\n"), Value::Null);
+    }
+
+    fn parse_err(source: &str) -> Error {
+        match parse(source) {
+            Ok(value) => panic!("expected an error, got {}", value),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn rejects_anchors_and_aliases() {
+        let e = parse_err("a: &x 1\nb: 2\n");
+        assert_eq!(e.line, 1);
+        assert!(e.message.contains("anchors and aliases"), "{}", e);
+
+        let e = parse_err("a: 1\nb: *x\n");
+        assert_eq!(e.line, 2);
+        assert!(e.message.contains("anchors and aliases"), "{}", e);
+
+        let e = parse_err("list:\n  - ok\n  - *x\n");
+        assert_eq!(e.line, 3);
+
+        let e = parse_err("a: [1, *x]\n");
+        assert_eq!(e.line, 1);
+    }
+
+    #[test]
+    fn rejects_unclosed_flow_collections() {
+        let e = parse_err("a: 1\nb: [1, 2\nc: 3\n");
+        assert_eq!(e.line, 2);
+    }
+
+    #[test]
+    fn rejects_invalid_structure() {
+        let e = parse_err("a: 1\nnot a mapping\n");
+        assert_eq!(e.line, 2);
+        assert!(e.message.contains("expected 'key: value'"), "{}", e);
+
+        let e = parse_err("a:\n    b: 1\n  c: 2\n");
+        assert_eq!(e.line, 3);
+
+        let e = parse_err("a: [1, 2] x\n");
+        assert_eq!(e.line, 1);
+    }
+}